@@ -0,0 +1,148 @@
+//! Functions for ledgers implementing the ICRC-1 token standard.
+//!
+//! ```
+//! # async fn run(agent: &ic_agent::Agent, ledger_id: candid::Principal, to: candid::Principal) {
+//! use ic_test_utils::canister::{icrc1::Account, Canister};
+//!
+//! let ledger = Canister::new_ledger(agent, ledger_id);
+//! let to = Account::new(to, None);
+//! let block_index = ledger.icrc1_transfer(to, 100_000_000u64.into()).await.unwrap();
+//! let balance = ledger.icrc1_balance_of(to).await.unwrap();
+//! # }
+//! ```
+use std::fmt;
+
+use candid::{CandidType, Deserialize, Encode, Nat, Principal};
+
+use super::{Ledger, Subaccount};
+use crate::canister::{decode, Canister};
+use crate::{get_waiter, Result};
+
+/// An ICRC-1 account, made of an owner and an optional subaccount.
+#[derive(Debug, Copy, Clone, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub struct Account {
+    /// Owner of the account
+    pub owner: Principal,
+    /// Subaccount of the owner. `None` is the default (all zero) subaccount.
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    /// Create a new account
+    pub fn new(owner: Principal, subaccount: Option<Subaccount>) -> Self {
+        Self { owner, subaccount }
+    }
+}
+
+/// Errors returned by the ledger `icrc1_transfer` method.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum TransferError {
+    /// The fee does not match the ledger fee
+    BadFee {
+        /// The fee expected by the ledger
+        expected_fee: Nat,
+    },
+    /// The amount to burn is below the minimum
+    BadBurn {
+        /// The minimum amount that can be burned
+        min_burn_amount: Nat,
+    },
+    /// The account does not hold enough tokens
+    InsufficientFunds {
+        /// Balance of the account
+        balance: Nat,
+    },
+    /// The transaction is older than the ledger allows
+    TooOld,
+    /// The transaction was created in the future
+    CreatedInFuture {
+        /// Time of the ledger in nanoseconds
+        ledger_time: u64,
+    },
+    /// The transaction is a duplicate of an earlier one
+    Duplicate {
+        /// Block index of the earlier transaction
+        duplicate_of: Nat,
+    },
+    /// The ledger is temporarily unavailable
+    TemporarilyUnavailable,
+    /// Any other error
+    GenericError {
+        /// Error code
+        error_code: Nat,
+        /// Error message
+        message: String,
+    },
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadFee { expected_fee } => write!(f, "Bad fee, expected {}", expected_fee),
+            Self::BadBurn { min_burn_amount } => {
+                write!(f, "Bad burn, minimum amount is {}", min_burn_amount)
+            }
+            Self::InsufficientFunds { balance } => {
+                write!(f, "Insufficient funds, balance is {}", balance)
+            }
+            Self::TooOld => write!(f, "Transaction too old"),
+            Self::CreatedInFuture { ledger_time } => write!(
+                f,
+                "Transaction created in the future, ledger time is {}",
+                ledger_time
+            ),
+            Self::Duplicate { duplicate_of } => {
+                write!(f, "Transaction is a duplicate of block {}", duplicate_of)
+            }
+            Self::TemporarilyUnavailable => write!(f, "Ledger temporarily unavailable"),
+            Self::GenericError {
+                error_code,
+                message,
+            } => write!(f, "Error {}: {}", error_code, message),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+impl<'agent> Canister<'agent, Ledger> {
+    /// Get the ICRC-1 balance of an account
+    pub async fn icrc1_balance_of(&self, account: Account) -> Result<Nat> {
+        let mut builder = self.agent.query(self.principal(), "icrc1_balance_of");
+        builder.with_arg(&Encode!(&account)?);
+        let data = builder.call().await?;
+        let balance = decode::<Nat>(&data)?;
+        Ok(balance)
+    }
+
+    /// Transfer tokens from the default subaccount of the caller using the
+    /// ledger fee. Returns the block index of the transfer.
+    ///
+    /// If the ledger rejects the transfer an
+    /// [`Error::Icrc1Ledger`](crate::Error::Icrc1Ledger) is returned.
+    pub async fn icrc1_transfer(&self, to: Account, amount: Nat) -> Result<Nat> {
+        let args = TransferArg {
+            from_subaccount: None,
+            to,
+            amount,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        let mut builder = self.agent.update(self.principal(), "icrc1_transfer");
+        builder.with_arg(&Encode!(&args)?);
+        let data = builder.call_and_wait(get_waiter()).await?;
+        let block_index = decode::<std::result::Result<Nat, TransferError>>(&data)??;
+        Ok(block_index)
+    }
+}
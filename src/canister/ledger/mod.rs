@@ -0,0 +1,535 @@
+//! Functions specific to the ICP ledger.
+//!
+//! The [`Ledger`] should be used together with a [`Canister`].
+//!
+//! ```
+//! # async fn run(agent: &ic_agent::Agent, ledger_id: candid::Principal, to: candid::Principal) {
+//! use ic_test_utils::canister::{AccountIdentifier, Canister, Tokens};
+//!
+//! let ledger = Canister::new_ledger(agent, ledger_id);
+//! let to = AccountIdentifier::new(to, None);
+//! let amount = Tokens::from_e8s(100_000_000);
+//! let fee = Tokens::from_e8s(10_000);
+//! let block_height = ledger.transfer(to, amount, fee, 0).await.unwrap();
+//! # }
+//! ```
+use std::fmt;
+
+use candid::types::{Serializer, Type};
+use candid::{CandidType, Deserialize, Encode, Principal};
+use ic_agent::Agent;
+use sha2::{Digest, Sha224};
+
+use super::{decode, Canister};
+use crate::{get_waiter, principal_for_identity};
+use crate::{Error, Result};
+
+pub mod icrc1;
+
+/// An amount of ICP, in e8s (10^-8 ICP).
+#[derive(Debug, Copy, Clone, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tokens {
+    e8s: u64,
+}
+
+impl Tokens {
+    /// Create a new amount of tokens from e8s
+    pub const fn from_e8s(e8s: u64) -> Self {
+        Self { e8s }
+    }
+
+    /// The amount in e8s
+    pub const fn get_e8s(&self) -> u64 {
+        self.e8s
+    }
+}
+
+/// Create an amount of tokens from e8s.
+pub const fn e8s(amount: u64) -> Tokens {
+    Tokens::from_e8s(amount)
+}
+
+/// Add two amounts of tokens, failing on overflow.
+#[allow(clippy::result_large_err)]
+pub fn tokens_add(a: Tokens, b: Tokens) -> Result<Tokens> {
+    match a.e8s.checked_add(b.e8s) {
+        Some(e8s) => Ok(Tokens::from_e8s(e8s)),
+        None => Err(Error::Generic(format!(
+            "Overflow adding {} e8s to {} e8s",
+            b.e8s, a.e8s
+        ))),
+    }
+}
+
+/// Subtract `b` from `a`, failing on underflow.
+#[allow(clippy::result_large_err)]
+pub fn tokens_sub(a: Tokens, b: Tokens) -> Result<Tokens> {
+    match a.e8s.checked_sub(b.e8s) {
+        Some(e8s) => Ok(Tokens::from_e8s(e8s)),
+        None => Err(Error::Generic(format!(
+            "Underflow subtracting {} e8s from {} e8s",
+            b.e8s, a.e8s
+        ))),
+    }
+}
+
+/// A subaccount of a principal on the ledger.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Subaccount(pub [u8; 32]);
+
+/// An account on the ledger, derived from a principal and a [`Subaccount`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AccountIdentifier([u8; 32]);
+
+impl AccountIdentifier {
+    /// Create the account identifier for a principal and an optional subaccount.
+    /// If no subaccount is given the default (all zero) subaccount is used.
+    pub fn new(owner: Principal, subaccount: Option<Subaccount>) -> Self {
+        let mut hasher = Sha224::new();
+        hasher.update(b"\x0Aaccount-id");
+        hasher.update(owner.as_slice());
+        hasher.update(subaccount.unwrap_or_default().0);
+        let hash = hasher.finalize();
+
+        let mut bytes = [0u8; 32];
+        bytes[..4].copy_from_slice(&crc32fast::hash(&hash).to_be_bytes());
+        bytes[4..].copy_from_slice(&hash);
+        Self(bytes)
+    }
+
+    /// The raw bytes of the account identifier
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Get the account identifier for a principal and an optional subaccount.
+pub fn account_identifier(
+    principal: Principal,
+    subaccount: Option<Subaccount>,
+) -> AccountIdentifier {
+    AccountIdentifier::new(principal, subaccount)
+}
+
+macro_rules! blob_decl {
+    ( $t: ty ) => {
+        impl CandidType for $t {
+            fn _ty() -> Type {
+                Type::Vec(Box::new(Type::Nat8))
+            }
+
+            fn idl_serialize<S>(&self, serializer: S) -> std::result::Result<(), S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_blob(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $t {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+                let bytes =
+                    <[u8; 32]>::try_from(bytes.as_slice()).map_err(serde::de::Error::custom)?;
+                Ok(Self(bytes))
+            }
+        }
+    };
+}
+
+blob_decl!(Subaccount);
+blob_decl!(AccountIdentifier);
+
+/// Errors returned by the ledger `transfer` method.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum TransferError {
+    /// The fee does not match the ledger fee
+    BadFee {
+        /// The fee expected by the ledger
+        expected_fee: Tokens,
+    },
+    /// The account does not hold enough tokens
+    InsufficientFunds {
+        /// Balance of the account
+        balance: Tokens,
+    },
+    /// The transaction is older than the ledger allows
+    TxTooOld {
+        /// Allowed age of a transaction in nanoseconds
+        allowed_window_nanos: u64,
+    },
+    /// The transaction was created in the future
+    TxCreatedInFuture,
+    /// The transaction is a duplicate of an earlier one
+    TxDuplicate {
+        /// Block height of the earlier transaction
+        duplicate_of: u64,
+    },
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadFee { expected_fee } => {
+                write!(f, "Bad fee, expected {} e8s", expected_fee.e8s)
+            }
+            Self::InsufficientFunds { balance } => {
+                write!(f, "Insufficient funds, balance is {} e8s", balance.e8s)
+            }
+            Self::TxTooOld {
+                allowed_window_nanos,
+            } => write!(
+                f,
+                "Transaction too old, allowed window is {}ns",
+                allowed_window_nanos
+            ),
+            Self::TxCreatedInFuture => write!(f, "Transaction created in the future"),
+            Self::TxDuplicate { duplicate_of } => {
+                write!(f, "Transaction is a duplicate of block {}", duplicate_of)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct TransferArgs {
+    memo: u64,
+    amount: Tokens,
+    fee: Tokens,
+    from_subaccount: Option<Subaccount>,
+    to: AccountIdentifier,
+    created_at_time: Option<TimeStamp>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct AccountBalanceArgs {
+    account: AccountIdentifier,
+}
+
+/// A point in time, as recorded by the ledger.
+#[derive(Debug, Copy, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TimeStamp {
+    /// Nanoseconds since the unix epoch
+    pub timestamp_nanos: u64,
+}
+
+/// An operation recorded in a ledger [`Transaction`].
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum Operation {
+    /// Tokens were minted
+    Mint {
+        /// Receiving account
+        to: AccountIdentifier,
+        /// Amount minted
+        amount: Tokens,
+    },
+    /// Tokens were burned
+    Burn {
+        /// Account the tokens were burned from
+        from: AccountIdentifier,
+        /// Account that burned the tokens on behalf of `from`, if any
+        spender: Option<AccountIdentifier>,
+        /// Amount burned
+        amount: Tokens,
+    },
+    /// Tokens were transferred
+    Transfer {
+        /// Sending account
+        from: AccountIdentifier,
+        /// Receiving account
+        to: AccountIdentifier,
+        /// Amount transferred
+        amount: Tokens,
+        /// Fee paid by the sender
+        fee: Tokens,
+        /// Account that made the transfer on behalf of `from`, if any
+        spender: Option<Vec<u8>>,
+    },
+    /// An allowance was approved
+    Approve {
+        /// Account granting the allowance
+        from: AccountIdentifier,
+        /// Account allowed to spend
+        spender: AccountIdentifier,
+        /// The approved allowance in e8s
+        allowance_e8s: candid::Int,
+        /// Fee paid by `from`
+        fee: Tokens,
+        /// When the allowance expires
+        expires_at: Option<TimeStamp>,
+        /// The allowance `from` expected to replace
+        expected_allowance: Option<Tokens>,
+    },
+    /// Tokens were transferred by a spender using an allowance
+    TransferFrom {
+        /// Sending account
+        from: AccountIdentifier,
+        /// Receiving account
+        to: AccountIdentifier,
+        /// Account that made the transfer
+        spender: AccountIdentifier,
+        /// Amount transferred
+        amount: Tokens,
+        /// Fee paid by the sender
+        fee: Tokens,
+    },
+}
+
+/// A transaction recorded in a ledger [`Block`].
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Transaction {
+    /// Memo of the transaction
+    pub memo: u64,
+    /// ICRC-1 memo of the transaction
+    pub icrc1_memo: Option<Vec<u8>>,
+    /// [`Operation`]
+    pub operation: Option<Operation>,
+    /// Time the transaction was created
+    pub created_at_time: TimeStamp,
+}
+
+/// A block of the ledger, as returned by [`Canister::query_blocks`].
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Block {
+    /// Hash of the parent block
+    pub parent_hash: Option<Vec<u8>>,
+    /// [`Transaction`]
+    pub transaction: Transaction,
+    /// Time the block was created
+    pub timestamp: TimeStamp,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+struct GetBlocksArgs {
+    start: u64,
+    length: u64,
+}
+
+// Only the blocks are decoded, the remaining fields are skipped.
+#[derive(Debug, CandidType, Deserialize)]
+struct QueryBlocksResponse {
+    blocks: Vec<Block>,
+}
+
+/// ICP ledger
+pub struct Ledger;
+
+impl<'agent> Canister<'agent, Ledger> {
+    /// Create a new ledger canister
+    pub fn new_ledger(agent: &'agent Agent, id: Principal) -> Self {
+        Self::new(id, agent)
+    }
+
+    /// Transfer tokens from the default subaccount of the caller.
+    /// Returns the block height of the transfer.
+    ///
+    /// If the ledger rejects the transfer an [`Error::Ledger`] is returned.
+    pub async fn transfer(
+        &self,
+        to: AccountIdentifier,
+        amount: Tokens,
+        fee: Tokens,
+        memo: u64,
+    ) -> Result<u64> {
+        let args = TransferArgs {
+            memo,
+            amount,
+            fee,
+            from_subaccount: None,
+            to,
+            created_at_time: None,
+        };
+        let mut builder = self.agent.update(self.principal(), "transfer");
+        builder.with_arg(&Encode!(&args)?);
+        let data = builder.call_and_wait(get_waiter()).await?;
+        let block_height = decode::<std::result::Result<u64, TransferError>>(&data)??;
+        Ok(block_height)
+    }
+
+    /// Mint tokens into an account. Returns the block height of the mint.
+    ///
+    /// The ledger only treats a transfer as a mint when it is sent from the
+    /// minting account, so the agent must use the minting account identity.
+    pub async fn mint(&self, to: AccountIdentifier, amount: Tokens) -> Result<u64> {
+        self.transfer(to, amount, Tokens::from_e8s(0), 0).await
+    }
+
+    /// Get the balance of an account
+    pub async fn account_balance(&self, account: AccountIdentifier) -> Result<Tokens> {
+        let mut builder = self.agent.query(self.principal(), "account_balance");
+        builder.with_arg(&Encode!(&AccountBalanceArgs { account })?);
+        let data = builder.call().await?;
+        let balance = decode::<Tokens>(&data)?;
+        Ok(balance)
+    }
+
+    /// Get the balances of a set of labelled accounts, in the order given.
+    /// This is useful to compare balances before and after a flow.
+    pub async fn balances_snapshot(
+        &self,
+        accounts: &[(String, AccountIdentifier)],
+    ) -> Result<Vec<(String, Tokens)>> {
+        let mut balances = Vec::with_capacity(accounts.len());
+        for (label, account) in accounts {
+            let balance = self.account_balance(*account).await?;
+            balances.push((label.clone(), balance));
+        }
+        Ok(balances)
+    }
+
+    /// Get the balance of the default account of a dfx identity
+    pub async fn balance_of_identity(&self, name: &str) -> Result<Tokens> {
+        let principal = principal_for_identity(name)?;
+        self.account_balance(account_identifier(principal, None))
+            .await
+    }
+
+    /// Get up to `length` blocks starting at block `start`.
+    /// Blocks that have been archived are not returned.
+    pub async fn query_blocks(&self, start: u64, length: u64) -> Result<Vec<Block>> {
+        let mut builder = self.agent.query(self.principal(), "query_blocks");
+        builder.with_arg(&Encode!(&GetBlocksArgs { start, length })?);
+        let data = builder.call().await?;
+        let response = decode::<QueryBlocksResponse>(&data)?;
+        Ok(response.blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `Operation` type as declared by current ICP ledgers.
+    #[derive(CandidType)]
+    #[allow(dead_code)]
+    enum LedgerOperation {
+        Mint {
+            to: Vec<u8>,
+            amount: Tokens,
+        },
+        Burn {
+            from: Vec<u8>,
+            spender: Option<Vec<u8>>,
+            amount: Tokens,
+        },
+        Transfer {
+            from: Vec<u8>,
+            to: Vec<u8>,
+            amount: Tokens,
+            fee: Tokens,
+            spender: Option<Vec<u8>>,
+        },
+        Approve {
+            from: Vec<u8>,
+            spender: Vec<u8>,
+            allowance_e8s: candid::Int,
+            allowance: Tokens,
+            fee: Tokens,
+            expires_at: Option<TimeStamp>,
+            expected_allowance: Option<Tokens>,
+        },
+        TransferFrom {
+            from: Vec<u8>,
+            to: Vec<u8>,
+            spender: Vec<u8>,
+            amount: Tokens,
+            fee: Tokens,
+        },
+    }
+
+    #[derive(CandidType)]
+    struct LedgerTransaction {
+        memo: u64,
+        icrc1_memo: Option<Vec<u8>>,
+        operation: Option<LedgerOperation>,
+        created_at_time: TimeStamp,
+    }
+
+    #[derive(CandidType)]
+    struct LedgerBlock {
+        parent_hash: Option<Vec<u8>>,
+        transaction: LedgerTransaction,
+        timestamp: TimeStamp,
+    }
+
+    #[derive(CandidType)]
+    struct QueryBlocks {
+        blocks: Vec<LedgerBlock>,
+    }
+
+    #[test]
+    fn account_identifier_of_the_anonymous_principal() {
+        let account = AccountIdentifier::new(Principal::anonymous(), None);
+        let hex: String = account
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            hex,
+            "1c7a48ba6a562aa9eaa2481a9049cdf0433b9738c992d698c31d8abf89cadc79"
+        );
+        assert_eq!(
+            account,
+            AccountIdentifier::new(Principal::anonymous(), Some(Subaccount([0; 32])))
+        );
+        assert_ne!(
+            account,
+            AccountIdentifier::new(Principal::anonymous(), Some(Subaccount([1; 32])))
+        );
+    }
+
+    #[test]
+    fn tokens_arithmetic() {
+        assert_eq!(tokens_add(e8s(1), e8s(2)).unwrap(), e8s(3));
+        assert_eq!(tokens_sub(e8s(3), e8s(2)).unwrap(), e8s(1));
+        assert!(matches!(
+            tokens_add(e8s(u64::MAX), e8s(1)),
+            Err(Error::Generic(_))
+        ));
+        assert!(matches!(tokens_sub(e8s(1), e8s(2)), Err(Error::Generic(_))));
+    }
+
+    #[test]
+    fn decodes_blocks_with_the_full_ledger_operation_type() {
+        let from = AccountIdentifier::new(Principal::anonymous(), None);
+        let to = AccountIdentifier::new(Principal::management_canister(), None);
+        let block = LedgerBlock {
+            parent_hash: Some(vec![1; 32]),
+            transaction: LedgerTransaction {
+                memo: 7,
+                icrc1_memo: None,
+                operation: Some(LedgerOperation::Transfer {
+                    from: from.as_bytes().to_vec(),
+                    to: to.as_bytes().to_vec(),
+                    amount: Tokens::from_e8s(100),
+                    fee: Tokens::from_e8s(10),
+                    spender: None,
+                }),
+                created_at_time: TimeStamp { timestamp_nanos: 1 },
+            },
+            timestamp: TimeStamp { timestamp_nanos: 2 },
+        };
+        let bytes = Encode!(&QueryBlocks {
+            blocks: vec![block]
+        })
+        .unwrap();
+
+        let response = decode::<QueryBlocksResponse>(&bytes).unwrap();
+        assert_eq!(
+            response.blocks[0].transaction.operation,
+            Some(Operation::Transfer {
+                from,
+                to,
+                amount: Tokens::from_e8s(100),
+                fee: Tokens::from_e8s(10),
+                spender: None,
+            })
+        );
+    }
+}
@@ -1,9 +1,15 @@
-use candid::{
-    encode_args, utils::ArgumentEncoder, CandidType, Deserialize, Encode, Principal,
-};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 
-use super::{Agent, Canister};
-use crate::{get_waiter, Result};
+use candid::{encode_args, utils::ArgumentEncoder, CandidType, Deserialize, Encode, Principal};
+use flate2::{write::GzEncoder, Compression};
+use futures_util::future::try_join_all;
+use garcon::Waiter;
+use sha2::{Digest, Sha256};
+
+use super::{decode, Agent, Canister, ComputeAllocation, MemoryAllocation, UpdateBuilder, Wallet};
+use crate::{get_waiter, get_waiter_with, Error, Result};
 
 /// The install mode of the canister to install. If a canister is already installed,
 /// using [InstallMode::Install] will be an error. [InstallMode::Reinstall] overwrites
@@ -36,6 +42,145 @@ pub struct CanisterInstall {
     pub arg: Vec<u8>,
 }
 
+/// The status of a canister, as returned by [`Canister::canister_status`].
+#[derive(Debug, Copy, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub enum CanisterStatus {
+    /// The canister is running
+    #[serde(rename = "running")]
+    Running,
+    /// The canister is in the process of stopping
+    #[serde(rename = "stopping")]
+    Stopping,
+    /// The canister is stopped
+    #[serde(rename = "stopped")]
+    Stopped,
+}
+
+/// The settings of an existing canister.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct DefiniteCanisterSettings {
+    /// Controllers of the canister
+    pub controllers: Vec<Principal>,
+    /// Compute allocation as a percentage
+    pub compute_allocation: u128,
+    /// Memory allocation in bytes
+    pub memory_allocation: u128,
+    /// Freezing threshold in seconds
+    pub freezing_threshold: u128,
+}
+
+/// The result of a [`Canister::canister_status`] call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct CanisterStatusResult {
+    /// [`CanisterStatus`]
+    pub status: CanisterStatus,
+    /// [`DefiniteCanisterSettings`]
+    pub settings: DefiniteCanisterSettings,
+    /// Hash of the installed wasm module, if any
+    pub module_hash: Option<Vec<u8>>,
+    /// Memory used by the canister in bytes
+    pub memory_size: u128,
+    /// Cycle balance of the canister
+    pub cycles: u128,
+}
+
+/// Settings for [`Canister::update_settings`] and
+/// [`Canister::create_canister_with_settings`].
+#[derive(Debug, Clone, Default)]
+pub struct CanisterSettings {
+    /// Controllers of the canister
+    pub controllers: Option<Vec<Principal>>,
+    /// [`ComputeAllocation`]
+    pub compute_allocation: Option<ComputeAllocation>,
+    /// [`MemoryAllocation`]
+    pub memory_allocation: Option<MemoryAllocation>,
+    /// Freezing threshold in seconds
+    pub freezing_threshold: Option<u64>,
+}
+
+// The candid representation of `CanisterSettings`, where every number is a `nat`.
+#[derive(CandidType)]
+pub(crate) struct RawCanisterSettings {
+    controllers: Option<Vec<Principal>>,
+    compute_allocation: Option<u128>,
+    memory_allocation: Option<u128>,
+    freezing_threshold: Option<u128>,
+}
+
+impl From<CanisterSettings> for RawCanisterSettings {
+    fn from(settings: CanisterSettings) -> Self {
+        Self {
+            controllers: settings.controllers,
+            compute_allocation: settings
+                .compute_allocation
+                .map(|alloc| u8::from(alloc).into()),
+            memory_allocation: settings
+                .memory_allocation
+                .map(|alloc| u64::from(alloc).into()),
+            freezing_threshold: settings.freezing_threshold.map(Into::into),
+        }
+    }
+}
+
+/// The curve of a threshold ECDSA key.
+#[derive(Debug, Copy, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub enum EcdsaCurve {
+    /// secp256k1 curve
+    #[serde(rename = "secp256k1")]
+    Secp256k1,
+}
+
+/// Id of a threshold ECDSA key.
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub struct EcdsaKeyId {
+    /// [`EcdsaCurve`]
+    pub curve: EcdsaCurve,
+    /// Name of the key, e.g. `dfx_test_key` on a local replica
+    pub name: String,
+}
+
+/// The result of a [`Canister::ecdsa_public_key`] call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct EcdsaPublicKeyResult {
+    #[serde(with = "serde_bytes")]
+    /// SEC1 encoded public key
+    pub public_key: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    /// Chain code for BIP32 derivation
+    pub chain_code: Vec<u8>,
+}
+
+/// Read a wasm module from disk.
+#[allow(clippy::result_large_err)]
+pub fn read_wasm(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    match std::fs::read(path) {
+        Ok(bytecode) => Ok(bytecode),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(Error::WasmNotFound(path.to_path_buf()))
+        }
+        Err(source) => Err(Error::FileRead {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Gzip a wasm module, unless it's already gzipped.
+#[allow(clippy::result_large_err)]
+fn gzip_wasm(wasm: Vec<u8>) -> Result<Vec<u8>> {
+    if wasm.starts_with(&GZIP_MAGIC) {
+        return Ok(wasm);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&wasm)?;
+    let compressed = encoder.finish()?;
+    Ok(compressed)
+}
+
 #[derive(CandidType, Deserialize)]
 struct In {
     canister_id: Principal,
@@ -48,12 +193,23 @@ struct In {
 /// The management canister is used to install code, upgrade, stop and delete
 /// canisters.
 ///
+/// When this is the real management canister (`aaaaa-aa`), calls made directly
+/// by the agent that target a canister (install, stop, start, delete,
+/// uninstall, status, settings and top ups) set the effective canister id to
+/// that canister, so a boundary node can route them to its subnet. A proxy
+/// created with [`Canister::new_management_with_id`] keeps the default
+/// effective canister id, as the replica requires it to be the receiver.
+/// Calls forwarded through a wallet (`deposit_cycles`, `raw_rand` and
+/// `ecdsa_public_key`) are routed to the wallet and don't need it, and
+/// `provisional_create_canister` has no target canister.
+///
 /// ```
 /// # use ic_agent::Agent;
 /// use ic_test_utils::canister::Canister;
-/// # async fn run(agent: &Agent, principal: ic_cdk::export::candid::Principal) {
+/// # async fn run(agent: &Agent, principal: candid::Principal) {
 /// let management = Canister::new_management(agent);
 /// management.stop_canister(&agent, principal).await;
+/// management.start_canister(&agent, principal).await;
 /// # }
 /// ```
 pub struct Management;
@@ -65,31 +221,128 @@ impl<'agent> Canister<'agent, Management> {
         Self::new(id, agent)
     }
 
-    async fn _install_code<'wallet_agent, T: ArgumentEncoder>(
+    /// Create a new management canister with a custom id.
+    /// This is useful when management calls are routed through a proxy canister.
+    pub fn new_management_with_id(agent: &'agent Agent, id: Principal) -> Self {
+        Self::new(id, agent)
+    }
+
+    // Start an update call about `canister_id`. Calls to the real management
+    // canister are routed by the target canister, a proxy is routed by itself.
+    fn update_for<'a>(
+        &self,
+        agent: &'a Agent,
+        method_name: &str,
+        canister_id: Principal,
+    ) -> UpdateBuilder<'a> {
+        let mut builder = agent.update(self.principal(), method_name);
+        if *self.principal() == Principal::management_canister() {
+            builder.with_effective_canister_id(canister_id);
+        }
+        builder
+    }
+
+    async fn _install_code<T: ArgumentEncoder>(
         &self,
         agent: &Agent,
         canister_id: Principal,
         bytecode: Vec<u8>,
         mode: InstallMode,
         arg: T,
+        waiter: garcon::Delay,
+    ) -> Result<()> {
+        let arg = encode_args(arg)?;
+        self._install_code_raw(agent, canister_id, bytecode, mode, arg, waiter)
+            .await
+    }
+
+    async fn _install_code_raw(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        bytecode: Vec<u8>,
+        mode: InstallMode,
+        arg: Vec<u8>,
+        waiter: garcon::Delay,
     ) -> Result<()> {
         let install_args = CanisterInstall {
             mode,
             canister_id,
             wasm_module: bytecode,
-            arg: encode_args(arg)?,
+            arg,
         };
 
         let args = Encode!(&install_args)?;
-        agent
-            .update(&Principal::management_canister(), "install_code")
+        self.update_for(agent, "install_code", canister_id)
             .with_arg(args)
-            .call_and_wait(get_waiter())
+            .call_and_wait(waiter)
             .await?;
 
         Ok(())
     }
 
+    /// Create a canister with cycles minted out of thin air, without a wallet.
+    /// This only works on a local replica, the IC rejects the call.
+    pub async fn provisional_create_canister(
+        &self,
+        agent: &Agent,
+        cycles: u128,
+        settings: Option<CanisterSettings>,
+    ) -> Result<Principal> {
+        #[derive(CandidType)]
+        struct In {
+            amount: Option<u128>,
+            settings: Option<RawCanisterSettings>,
+        }
+
+        #[derive(CandidType, Deserialize)]
+        struct Out {
+            canister_id: Principal,
+        }
+
+        let arg = Encode!(&In {
+            amount: Some(cycles),
+            settings: settings.map(Into::into),
+        })?;
+        let data = agent
+            .update(self.principal(), "provisional_create_canister_with_cycles")
+            .with_arg(arg)
+            .call_and_wait(get_waiter())
+            .await
+            .map_err(|e| Error::Call {
+                method: "provisional_create_canister_with_cycles".to_string(),
+                canister: *self.principal(),
+                source: Box::new(e.into()),
+            })?;
+        let out = decode::<Out>(&data)?;
+        Ok(out.canister_id)
+    }
+
+    /// Add cycles minted out of thin air to a canister, without a wallet.
+    /// This only works on a local replica, the IC rejects the call.
+    pub async fn provisional_top_up(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        amount: u128,
+    ) -> Result<()> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+            amount: u128,
+        }
+
+        let arg = Encode!(&In {
+            canister_id,
+            amount
+        })?;
+        self.update_for(agent, "provisional_top_up_canister", canister_id)
+            .with_arg(arg)
+            .call_and_wait(get_waiter())
+            .await?;
+        Ok(())
+    }
+
     /// Install code in an existing canister.
     /// To create a canister first use [`Canister::create_canister`]
     pub async fn install_code<T: ArgumentEncoder>(
@@ -99,10 +352,218 @@ impl<'agent> Canister<'agent, Management> {
         bytecode: Vec<u8>,
         arg: T,
     ) -> Result<()> {
-        self._install_code(agent, canister_id, bytecode, InstallMode::Install, arg)
+        self.install_code_with_waiter(agent, canister_id, bytecode, arg, get_waiter())
             .await
     }
 
+    /// Install code in an existing canister, waiting for the call with the
+    /// given `waiter` instead of the default five minute [`get_waiter`].
+    pub async fn install_code_with_waiter<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        bytecode: Vec<u8>,
+        arg: T,
+        waiter: garcon::Delay,
+    ) -> Result<()> {
+        self._install_code(
+            agent,
+            canister_id,
+            bytecode,
+            InstallMode::Install,
+            arg,
+            waiter,
+        )
+        .await
+    }
+
+    /// Install code in an existing canister with an already candid encoded
+    /// init argument, e.g. one produced by a build script.
+    pub async fn install_code_raw(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        bytecode: Vec<u8>,
+        arg_bytes: Vec<u8>,
+    ) -> Result<()> {
+        self._install_code_raw(
+            agent,
+            canister_id,
+            bytecode,
+            InstallMode::Install,
+            arg_bytes,
+            get_waiter(),
+        )
+        .await
+    }
+
+    /// Install code in many existing canisters concurrently.
+    /// Each install is a `(canister_id, bytecode, arg)` tuple.
+    ///
+    /// If any install fails the error names the canister it failed for.
+    pub async fn install_code_many<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        installs: Vec<(Principal, Vec<u8>, T)>,
+    ) -> Result<()> {
+        let installs = installs
+            .into_iter()
+            .map(|(canister_id, bytecode, arg)| async move {
+                self.install_code(agent, canister_id, bytecode, arg)
+                    .await
+                    .map_err(|e| Error::Call {
+                        method: "install_code".to_string(),
+                        canister: canister_id,
+                        source: Box::new(e),
+                    })
+            });
+        try_join_all(installs).await?;
+        Ok(())
+    }
+
+    /// Install code from a wasm file in an existing canister.
+    pub async fn install_code_from_file<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        path: impl AsRef<Path>,
+        arg: T,
+    ) -> Result<()> {
+        let bytecode = read_wasm(path)?;
+        self.install_code(agent, canister_id, bytecode, arg).await
+    }
+
+    /// Gzip a wasm module and install it in an existing canister.
+    /// Modules that are already gzipped are installed as is.
+    /// The replica accepts both gzipped and plain wasm modules, so this
+    /// only reduces the size of the ingress message.
+    pub async fn install_code_gzip<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        wasm: Vec<u8>,
+        arg: T,
+    ) -> Result<()> {
+        let bytecode = gzip_wasm(wasm)?;
+        self.install_code(agent, canister_id, bytecode, arg).await
+    }
+
+    /// Install code that is too large for a single ingress message in an
+    /// existing canister. The wasm is uploaded in chunks of 1 MiB to the
+    /// chunk store of the canister, and then installed from there.
+    /// The chunk store is cleared afterwards.
+    pub async fn install_chunked_code<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        wasm: &[u8],
+        arg: T,
+    ) -> Result<()> {
+        self._install_chunked_code(agent, canister_id, wasm, InstallMode::Install, arg)
+            .await
+    }
+
+    /// Like [`Canister::install_chunked_code`], but replaces the code of the
+    /// canister and erases all its state.
+    pub async fn reinstall_chunked_code<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        wasm: &[u8],
+        arg: T,
+    ) -> Result<()> {
+        self._install_chunked_code(agent, canister_id, wasm, InstallMode::Reinstall, arg)
+            .await
+    }
+
+    /// Like [`Canister::install_chunked_code`], but upgrades the canister.
+    pub async fn upgrade_chunked_code<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        wasm: &[u8],
+        arg: T,
+    ) -> Result<()> {
+        self._install_chunked_code(agent, canister_id, wasm, InstallMode::Upgrade, arg)
+            .await
+    }
+
+    async fn _install_chunked_code<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        wasm: &[u8],
+        mode: InstallMode,
+        arg: T,
+    ) -> Result<()> {
+        #[derive(CandidType, Deserialize)]
+        struct UploadChunkArgs {
+            canister_id: Principal,
+            #[serde(with = "serde_bytes")]
+            chunk: Vec<u8>,
+        }
+
+        #[derive(CandidType, Deserialize)]
+        struct ChunkHash {
+            #[serde(with = "serde_bytes")]
+            hash: Vec<u8>,
+        }
+
+        #[derive(CandidType, Deserialize)]
+        struct InstallChunkedCodeArgs {
+            mode: InstallMode,
+            target_canister: Principal,
+            store_canister: Option<Principal>,
+            chunk_hashes_list: Vec<ChunkHash>,
+            #[serde(with = "serde_bytes")]
+            wasm_module_hash: Vec<u8>,
+            #[serde(with = "serde_bytes")]
+            arg: Vec<u8>,
+            sender_canister_version: Option<u64>,
+        }
+
+        let mut chunk_hashes_list = Vec::new();
+        for chunk in wasm.chunks(CHUNK_SIZE) {
+            let args = Encode!(&UploadChunkArgs {
+                canister_id,
+                chunk: chunk.to_vec(),
+            })?;
+            let data = self
+                .update_for(agent, "upload_chunk", canister_id)
+                .with_arg(args)
+                .call_and_wait(get_waiter())
+                .await?;
+            chunk_hashes_list.push(decode::<ChunkHash>(&data)?);
+        }
+
+        let install_args = InstallChunkedCodeArgs {
+            mode,
+            target_canister: canister_id,
+            store_canister: None,
+            chunk_hashes_list,
+            wasm_module_hash: Sha256::digest(wasm).to_vec(),
+            arg: encode_args(arg)?,
+            sender_canister_version: None,
+        };
+        let args = Encode!(&install_args)?;
+        let installed = self
+            .update_for(agent, "install_chunked_code", canister_id)
+            .with_arg(args)
+            .call_and_wait(get_waiter())
+            .await;
+
+        // The chunks count against the memory of the canister until cleared,
+        // so clear them whether the install worked or not.
+        let cleared = self
+            .update_for(agent, "clear_chunk_store", canister_id)
+            .with_arg(Encode!(&In { canister_id })?)
+            .call_and_wait(get_waiter())
+            .await;
+        installed?;
+        cleared?;
+        Ok(())
+    }
+
     /// Replaces code of an existing canister. This method completely erases the old canister with
     /// all its state. If you want to upgrade the canister, call [`Canister::upgrade_code`] instead.
     pub async fn reinstall_code<T: ArgumentEncoder>(
@@ -112,8 +573,15 @@ impl<'agent> Canister<'agent, Management> {
         bytecode: Vec<u8>,
         arg: T,
     ) -> Result<()> {
-        self._install_code(agent, canister_id, bytecode, InstallMode::Reinstall, arg)
-            .await
+        self._install_code(
+            agent,
+            canister_id,
+            bytecode,
+            InstallMode::Reinstall,
+            arg,
+            get_waiter(),
+        )
+        .await
     }
 
     /// Upgrade an existing canister.
@@ -125,19 +593,65 @@ impl<'agent> Canister<'agent, Management> {
         bytecode: Vec<u8>,
         arg: T,
     ) -> Result<()> {
-        self._install_code(agent, canister_id, bytecode, InstallMode::Upgrade, arg)
+        self.upgrade_code_with_waiter(agent, canister_id, bytecode, arg, get_waiter())
             .await
     }
 
+    /// Upgrade an existing canister, waiting for the call with the given
+    /// `waiter` instead of the default five minute [`get_waiter`].
+    pub async fn upgrade_code_with_waiter<T: ArgumentEncoder>(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        bytecode: Vec<u8>,
+        arg: T,
+        waiter: garcon::Delay,
+    ) -> Result<()> {
+        self._install_code(
+            agent,
+            canister_id,
+            bytecode,
+            InstallMode::Upgrade,
+            arg,
+            waiter,
+        )
+        .await
+    }
+
     /// Stop a running canister
     pub async fn stop_canister(
         &self,
         agent: &Agent,
         canister_id: Principal, // canister to stop
+    ) -> Result<()> {
+        self.stop_canister_with_waiter(agent, canister_id, get_waiter())
+            .await
+    }
+
+    /// Stop a canister, waiting for the call with the given `waiter` instead
+    /// of the default five minute [`get_waiter`].
+    pub async fn stop_canister_with_waiter(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        waiter: garcon::Delay,
+    ) -> Result<()> {
+        let arg = Encode!(&In { canister_id })?;
+        self.update_for(agent, "stop_canister", canister_id)
+            .with_arg(arg)
+            .call_and_wait(waiter)
+            .await?;
+        Ok(())
+    }
+
+    /// Start a stopped canister
+    pub async fn start_canister(
+        &self,
+        agent: &Agent,
+        canister_id: Principal, // canister to start
     ) -> Result<()> {
         let arg = Encode!(&In { canister_id })?;
-        agent
-            .update(&Principal::management_canister(), "stop_canister")
+        self.update_for(agent, "start_canister", canister_id)
             .with_arg(arg)
             .call_and_wait(get_waiter())
             .await?;
@@ -151,12 +665,258 @@ impl<'agent> Canister<'agent, Management> {
         agent: &Agent,
         canister_id: Principal, // canister to delete
     ) -> Result<()> {
+        self.delete_canister_with_waiter(agent, canister_id, get_waiter())
+            .await
+    }
+
+    /// Delete a canister, waiting for the call with the given `waiter` instead
+    /// of the default five minute [`get_waiter`].
+    pub async fn delete_canister_with_waiter(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        waiter: garcon::Delay,
+    ) -> Result<()> {
+        let arg = Encode!(&In { canister_id })?;
+        self.update_for(agent, "delete_canister", canister_id)
+            .with_arg(arg)
+            .call_and_wait(waiter)
+            .await?;
+        Ok(())
+    }
+
+    /// Remove the code and state of a canister, keeping its id and cycles.
+    pub async fn uninstall_code(
+        &self,
+        agent: &Agent,
+        canister_id: Principal, // canister to uninstall
+    ) -> Result<()> {
+        let arg = Encode!(&In { canister_id })?;
+        self.update_for(agent, "uninstall_code", canister_id)
+            .with_arg(arg)
+            .call_and_wait(get_waiter())
+            .await?;
+        Ok(())
+    }
+
+    /// Deposit cycles from a wallet into a canister through the management canister.
+    pub async fn deposit_cycles(
+        &self,
+        wallet: &Canister<'_, Wallet>,
+        canister_id: Principal,
+        cycles: u64,
+    ) -> Result<()> {
+        let mut call = self.agent.update(self.principal(), "deposit_cycles");
+        call.with_arg(Encode!(&In { canister_id })?);
+        wallet.call_forward(call, cycles).await?;
+        Ok(())
+    }
+
+    /// Get 32 random bytes from the management canister.
+    /// The management canister only accepts `raw_rand` calls from canisters,
+    /// so the call is forwarded through the wallet.
+    pub async fn raw_rand(&self, wallet: &Canister<'_, Wallet>) -> Result<Vec<u8>> {
+        let mut call = self.agent.update(self.principal(), "raw_rand");
+        call.with_arg(Encode!()?);
+        let data = wallet.call_forward(call, 0).await?;
+        let bytes = decode::<Vec<u8>>(&data)?;
+        Ok(bytes)
+    }
+
+    /// Get a threshold ECDSA public key.
+    /// If `canister_id` is `None` the key of the wallet is returned.
+    /// The management canister only accepts `ecdsa_public_key` calls from canisters,
+    /// so the call is forwarded through the wallet.
+    pub async fn ecdsa_public_key(
+        &self,
+        wallet: &Canister<'_, Wallet>,
+        canister_id: Option<Principal>,
+        derivation_path: Vec<Vec<u8>>,
+        key_id: EcdsaKeyId,
+    ) -> Result<EcdsaPublicKeyResult> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Option<Principal>,
+            derivation_path: Vec<Vec<u8>>,
+            key_id: EcdsaKeyId,
+        }
+
+        let mut call = self.agent.update(self.principal(), "ecdsa_public_key");
+        call.with_arg(Encode!(&In {
+            canister_id,
+            derivation_path,
+            key_id,
+        })?);
+        let data = wallet.call_forward(call, 0).await?;
+        let result = decode::<EcdsaPublicKeyResult>(&data)?;
+        Ok(result)
+    }
+
+    /// Update the settings of a canister.
+    /// Only the settings that are `Some` are changed.
+    pub async fn update_settings(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        settings: CanisterSettings,
+    ) -> Result<()> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+            settings: RawCanisterSettings,
+        }
+
+        let arg = Encode!(&In {
+            canister_id,
+            settings: settings.into(),
+        })?;
+        self.update_for(agent, "update_settings", canister_id)
+            .with_arg(arg)
+            .call_and_wait(get_waiter())
+            .await?;
+        Ok(())
+    }
+
+    /// Replace the controllers of a canister, leaving the other settings unchanged.
+    pub async fn set_controllers(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        controllers: Vec<Principal>,
+    ) -> Result<()> {
+        let settings = CanisterSettings {
+            controllers: Some(controllers),
+            ..Default::default()
+        };
+        self.update_settings(agent, canister_id, settings).await
+    }
+
+    /// Get the status of a canister
+    pub async fn canister_status(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+    ) -> Result<CanisterStatusResult> {
         let arg = Encode!(&In { canister_id })?;
-        agent
-            .update(&Principal::management_canister(), "delete_canister")
+        let data = self
+            .update_for(agent, "canister_status", canister_id)
             .with_arg(arg)
             .call_and_wait(get_waiter())
             .await?;
+        let status = decode::<CanisterStatusResult>(&data)?;
+        Ok(status)
+    }
+
+    /// Get the controllers of a canister
+    pub async fn get_controllers(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+    ) -> Result<Vec<Principal>> {
+        let status = self.canister_status(agent, canister_id).await?;
+        Ok(status.settings.controllers)
+    }
+
+    /// Poll the status of a canister until it is stopped.
+    /// Returns an error if the canister is not stopped within `timeout`.
+    pub async fn wait_until_stopped(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut waiter = get_waiter_with(Duration::from_millis(500), timeout);
+        waiter.start();
+        loop {
+            let status = self.canister_status(agent, canister_id).await?;
+            if status.status == CanisterStatus::Stopped {
+                return Ok(());
+            }
+            waiter.async_wait().await.map_err(|_| Error::Timeout {
+                operation: format!("canister {} to stop", canister_id),
+                waited: timeout,
+            })?;
+        }
+    }
+
+    /// Get the hash of the wasm module installed in a canister,
+    /// or `None` if the canister is empty.
+    pub async fn module_hash(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+    ) -> Result<Option<Vec<u8>>> {
+        let status = self.canister_status(agent, canister_id).await?;
+        Ok(status.module_hash)
+    }
+
+    /// Check if a principal is one of the controllers of a canister.
+    pub async fn is_controller(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        principal: Principal,
+    ) -> Result<bool> {
+        let controllers = self.get_controllers(agent, canister_id).await?;
+        Ok(controllers.contains(&principal))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Assertions -
+// -----------------------------------------------------------------------------
+
+#[cfg(feature = "asserts")]
+impl<'agent> Canister<'agent, Management> {
+    /// Return an error unless the canister is running.
+    pub async fn assert_running(&self, agent: &Agent, canister_id: Principal) -> Result<()> {
+        self.assert_status(agent, canister_id, CanisterStatus::Running)
+            .await
+    }
+
+    /// Return an error unless the canister is stopped.
+    pub async fn assert_stopped(&self, agent: &Agent, canister_id: Principal) -> Result<()> {
+        self.assert_status(agent, canister_id, CanisterStatus::Stopped)
+            .await
+    }
+
+    async fn assert_status(
+        &self,
+        agent: &Agent,
+        canister_id: Principal,
+        expected: CanisterStatus,
+    ) -> Result<()> {
+        let actual = self.canister_status(agent, canister_id).await?.status;
+        if actual != expected {
+            return Err(Error::UnexpectedCanisterStatus {
+                canister: canister_id,
+                expected,
+                actual,
+            });
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn gzip_wasm_compresses_once() {
+        let wasm = b"\0asm\x01\0\0\0".to_vec();
+        let gzipped = gzip_wasm(wasm.clone()).unwrap();
+        assert!(gzipped.starts_with(&GZIP_MAGIC));
+
+        // Already gzipped modules are left alone
+        assert_eq!(gzip_wasm(gzipped.clone()).unwrap(), gzipped);
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(gzipped.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, wasm);
+    }
+}
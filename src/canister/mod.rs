@@ -3,27 +3,101 @@
 //! ```
 //! use ic_test_utils::canister::Canister;
 //!
-//! # async fn run<'a, T>(canister: Canister<'a, T>, principal: ic_cdk::export::candid::Principal, agent: &'a ic_agent::Agent) {
+//! # async fn run<'a, T>(canister: Canister<'a, T>, principal: candid::Principal, agent: &'a ic_agent::Agent) {
 //! let wallet = Canister::new_wallet(agent, "bob", None).unwrap();
 //! let management = Canister::new_management(agent);
 //! # }
 //! ```
+use std::fs::read_to_string;
+use std::future::Future;
 use std::marker::PhantomData;
+use std::mem::{discriminant, Discriminant};
+use std::path::PathBuf;
 
-use crate::Result;
-use candid::{CandidType, Encode};
-use ic_agent::agent::{Agent, QueryBuilder, UpdateBuilder};
-use ic_agent::ic_types::Principal;
+use crate::{find_up, get_waiter, Error, Result};
+use candid::{
+    encode_args,
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    CandidType, Deserialize, Encode, Principal,
+};
+use futures_util::stream::{self, Stream};
+use garcon::Waiter;
+use ic_agent::agent::{Agent, AgentError, QueryBuilder, RequestStatusResponse, UpdateBuilder};
+use ic_agent::RequestId;
 
+mod ledger;
 mod management;
 mod wallet;
 
-pub use management::Management;
-pub use wallet::Wallet;
+pub use ledger::icrc1;
+pub use ledger::{
+    account_identifier, e8s, tokens_add, tokens_sub, AccountIdentifier, Block, Ledger, Operation,
+    Subaccount, TimeStamp, Tokens, Transaction, TransferError,
+};
+pub use management::{
+    read_wasm, CanisterSettings, CanisterStatus, CanisterStatusResult, DefiniteCanisterSettings,
+    EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyResult, Management,
+};
+pub use wallet::{ComputeAllocation, CreateResult, MemoryAllocation, Wallet, WalletCallError};
+
+/// Default path of the json file mapping canister names to ids, relative to
+/// the dfx project. It is looked for in the current directory and then each
+/// of its parents.
+pub const CANISTER_IDS_PATH: &str = ".dfx/local/canister_ids.json";
+
+#[allow(clippy::result_large_err)]
+fn get_canister_principal<'a>(
+    name: &str,
+    canister_ids_path: impl Into<Option<&'a str>>,
+) -> Result<Principal> {
+    let canister_ids_path = match canister_ids_path.into() {
+        Some(path) => PathBuf::from(path),
+        None => find_up(CANISTER_IDS_PATH).unwrap_or_else(|| PathBuf::from(CANISTER_IDS_PATH)),
+    };
+    let json_str = read_to_string(&canister_ids_path).map_err(|source| Error::FileRead {
+        path: canister_ids_path,
+        source,
+    })?;
+    let json = serde_json::from_str::<serde_json::Value>(&json_str)?;
+    let id = json[name]["local"]
+        .as_str()
+        .ok_or(Error::InvalidOrMissingCanisterInJson)?;
+    let principal = Principal::from_text(id)?;
+    Ok(principal)
+}
+
+// Decode a single candid value, keeping the type and size of the reply in the error.
+#[allow(clippy::result_large_err)]
+pub(crate) fn decode<T>(bytes: &[u8]) -> Result<T>
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+{
+    candid::decode_one(bytes).map_err(|source| Error::Decode {
+        type_name: std::any::type_name::<T>(),
+        bytes_len: bytes.len(),
+        source,
+    })
+}
+
+// Like `decode`, for replies with several values, e.g. `(Vec<T>, u32)`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn decode_args<'a, T>(bytes: &'a [u8]) -> Result<T>
+where
+    T: ArgumentDecoder<'a>,
+{
+    candid::decode_args(bytes).map_err(|source| Error::Decode {
+        type_name: std::any::type_name::<T>(),
+        bytes_len: bytes.len(),
+        source,
+    })
+}
 
 /// Type alias for the management canister
 pub type ManagementCanister<'agent> = Canister<'agent, Management>;
 
+/// Type alias for the ledger canister
+pub type LedgerCanister<'agent> = Canister<'agent, Ledger>;
+
 /// Type alias for the wallet canister
 pub type WalletCanister<'agent> = Canister<'agent, Wallet>;
 
@@ -44,12 +118,61 @@ impl<'agent, T> Canister<'agent, T> {
         }
     }
 
+    /// Create a new canister by looking up its name in the canister ids json
+    /// written by dfx.
+    /// If the `canister_ids_path` is `None` then the default [`CANISTER_IDS_PATH`]
+    /// will be used.
+    #[allow(clippy::result_large_err)]
+    pub fn from_name<'a>(
+        agent: &'agent Agent,
+        name: &str,
+        canister_ids_path: impl Into<Option<&'a str>>,
+    ) -> Result<Self> {
+        let id = get_canister_principal(name, canister_ids_path)?;
+        Ok(Self::new(id, agent))
+    }
+
     /// The id of the canister
     pub fn principal(&self) -> &Principal {
         &self.id
     }
 
+    /// The agent used to talk to the canister, for calls not covered by
+    /// `Canister` itself.
+    pub fn agent(&self) -> &'agent Agent {
+        self.agent
+    }
+
+    /// Read a custom metadata section of the canister, e.g. `candid:service`.
+    ///
+    /// Private sections can only be read by the controllers of the canister.
+    pub async fn metadata(&self, name: &str) -> Result<Vec<u8>> {
+        self.agent
+            .read_state_canister_metadata(self.id, name, false)
+            .await
+            .map_err(|e| match e {
+                AgentError::LookupPathAbsent(_) => Error::MetadataNotFound(name.to_string()),
+                AgentError::HttpError(payload) if payload.status == 403 => {
+                    Error::MetadataPrivate(name.to_string())
+                }
+                e => Error::from(e),
+            })
+    }
+
+    /// Get the candid interface of the canister from its `candid:service` metadata
+    pub async fn candid_interface(&self) -> Result<String> {
+        let bytes = self.metadata("candid:service").await?;
+        let interface = String::from_utf8(bytes).map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(interface)
+    }
+
+    /// Get the same canister with a different marker type
+    pub fn as_type<U>(&self) -> Canister<'agent, U> {
+        Canister::new(self.id, self.agent)
+    }
+
     /// Update call to the canister
+    #[allow(clippy::result_large_err)]
     pub fn update<A: CandidType>(
         &self,
         method_name: impl Into<String>,
@@ -63,8 +186,291 @@ impl<'agent, T> Canister<'agent, T> {
         Ok(builder)
     }
 
+    /// Update call to the canister with multiple arguments
+    #[allow(clippy::result_large_err)]
+    pub fn update_args<A: ArgumentEncoder>(
+        &self,
+        method_name: impl Into<String>,
+        args: A,
+    ) -> Result<UpdateBuilder<'_>> {
+        let mut builder = self.agent.update(&self.id, method_name);
+        builder.with_arg(encode_args(args)?);
+        Ok(builder)
+    }
+
+    /// Make an update call, wait for it to complete and decode the reply.
+    pub async fn update_call<A, Out>(
+        &self,
+        method_name: impl Into<String>,
+        args: Option<A>,
+    ) -> Result<Out>
+    where
+        A: CandidType,
+        Out: CandidType + for<'de> Deserialize<'de>,
+    {
+        self.update(method_name, args)?.call_and_decode().await
+    }
+
+    /// Make an update call with cycles attached, by forwarding it through a wallet.
+    /// Returns the raw reply.
+    pub async fn update_with_cycles<A: CandidType>(
+        &self,
+        wallet: &Canister<'_, Wallet>,
+        method_name: impl Into<String>,
+        args: Option<A>,
+        cycles: u64,
+    ) -> Result<Vec<u8>> {
+        let call = self.update(method_name, args)?;
+        wallet.call_forward(call, cycles).await
+    }
+
+    /// Make an update call and wait for it to complete, discarding the reply.
+    /// This is useful for setup calls where only success matters.
+    pub async fn call_ok<A: CandidType>(
+        &self,
+        method_name: impl Into<String>,
+        args: Option<A>,
+    ) -> Result<()> {
+        let method_name = method_name.into();
+        self.update(method_name.as_str(), args)?
+            .call_and_wait(get_waiter())
+            .await
+            .map_err(|e| Error::Call {
+                method: method_name.clone(),
+                canister: self.id,
+                source: Box::new(e.into()),
+            })?;
+        Ok(())
+    }
+
+    /// Submit an update call and poll its status, yielding each status
+    /// transition, e.g. `Received`, `Processing` and finally `Replied`.
+    ///
+    /// The stream ends after the call is replied to or rejected.
+    pub fn call_and_poll<'a>(
+        &'a self,
+        builder: UpdateBuilder<'a>,
+    ) -> impl Stream<Item = Result<RequestStatusResponse>> + 'a {
+        stream::unfold(PollState::Submit(builder), move |state| async move {
+            let (request_id, effective_canister_id, last, mut waiter) = match state {
+                PollState::Done => return None,
+                PollState::Submit(builder) => match builder.call().await {
+                    Ok(request_id) => {
+                        let mut waiter = get_waiter();
+                        waiter.start();
+                        (request_id, builder.effective_canister_id, None, waiter)
+                    }
+                    Err(e) => return Some((Err(e.into()), PollState::Done)),
+                },
+                PollState::Poll {
+                    request_id,
+                    effective_canister_id,
+                    last,
+                    waiter,
+                } => (request_id, effective_canister_id, Some(last), waiter),
+            };
+
+            loop {
+                let status = match self
+                    .agent
+                    .request_status_raw(&request_id, effective_canister_id, false)
+                    .await
+                {
+                    Ok(status) => status,
+                    Err(e) => return Some((Err(e.into()), PollState::Done)),
+                };
+
+                let kind = discriminant(&status);
+                if last != Some(kind) {
+                    let next = match status {
+                        RequestStatusResponse::Replied { .. }
+                        | RequestStatusResponse::Rejected { .. }
+                        | RequestStatusResponse::Done => PollState::Done,
+                        _ => PollState::Poll {
+                            request_id,
+                            effective_canister_id,
+                            last: kind,
+                            waiter,
+                        },
+                    };
+                    return Some((Ok(status), next));
+                }
+
+                if waiter.async_wait().await.is_err() {
+                    let e = AgentError::TimeoutWaitingForResponse();
+                    return Some((Err(e.into()), PollState::Done));
+                }
+            }
+        })
+    }
+
     /// Query the canister
     pub fn query(&self, method_name: impl Into<String>) -> QueryBuilder<'_> {
         self.agent.query(&self.id, method_name)
     }
+
+    /// Query the canister and decode the reply.
+    pub async fn query_call<A, Out>(
+        &self,
+        method_name: impl Into<String>,
+        arg: Option<A>,
+    ) -> Result<Out>
+    where
+        A: CandidType,
+        Out: CandidType + for<'de> Deserialize<'de>,
+    {
+        let mut builder = self.agent.query(&self.id, method_name);
+        match arg {
+            Some(arg) => builder.with_arg(Encode!(&arg)?),
+            None => builder.with_arg(Encode!()?),
+        };
+        let data = builder.call().await?;
+        let out = decode::<Out>(&data)?;
+        Ok(out)
+    }
+
+    /// Call a method as an update, so it runs in replicated mode and its reply
+    /// is read from the certified state tree, verified against the agent's
+    /// root key.
+    ///
+    /// This is not a certified query: the call goes through consensus, so it
+    /// is slower, and if `method_name` is an update method its changes are
+    /// kept. The agent version used here does not verify query responses.
+    ///
+    /// Returns [`Error::CertificationFailed`] if the certificate does not verify.
+    pub async fn call_replicated<A: CandidType>(
+        &self,
+        method_name: impl Into<String>,
+        arg: Option<A>,
+    ) -> Result<Vec<u8>> {
+        let mut builder = self.agent.update(&self.id, method_name);
+        match arg {
+            Some(arg) => builder.with_arg(Encode!(&arg)?),
+            None => builder.with_arg(Encode!()?),
+        };
+        builder
+            .call_and_wait(get_waiter())
+            .await
+            .map_err(|e| match e {
+                AgentError::CertificateVerificationFailed()
+                | AgentError::CertificateNotAuthorized() => Error::CertificationFailed(self.id),
+                e => Error::from(e),
+            })
+    }
+
+    /// Query the canister with an argument
+    #[allow(clippy::result_large_err)]
+    pub fn query_with_arg<A: CandidType>(
+        &self,
+        method_name: impl Into<String>,
+        arg: A,
+    ) -> Result<QueryBuilder<'_>> {
+        let mut builder = self.agent.query(&self.id, method_name);
+        builder.with_arg(Encode!(&arg)?);
+        Ok(builder)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Update builder -
+// -----------------------------------------------------------------------------
+
+// State of the stream returned by `Canister::call_and_poll`.
+enum PollState<'a> {
+    Submit(UpdateBuilder<'a>),
+    Poll {
+        request_id: RequestId,
+        effective_canister_id: Principal,
+        last: Discriminant<RequestStatusResponse>,
+        waiter: garcon::Delay,
+    },
+    Done,
+}
+
+/// Extension methods for the [`UpdateBuilder`] returned by [`Canister::update`].
+///
+/// `UpdateBuilder` already has `with_effective_canister_id` and `expire_after`,
+/// which chain with these methods.
+///
+/// ```
+/// # async fn run<T>(canister: ic_test_utils::Canister<'_, T>, id: candid::Principal) {
+/// use ic_test_utils::canister::UpdateBuilderExt;
+///
+/// let count: u64 = canister
+///     .update("increment", Some(1u64))
+///     .unwrap()
+///     .with_effective_canister_id(id)
+///     .call_and_decode()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+pub trait UpdateBuilderExt {
+    /// Make the update call, wait for it to complete and decode the reply.
+    fn call_and_decode<Out>(&self) -> impl Future<Output = Result<Out>> + Send
+    where
+        Out: CandidType + for<'de> Deserialize<'de>;
+}
+
+impl UpdateBuilderExt for UpdateBuilder<'_> {
+    async fn call_and_decode<Out>(&self) -> Result<Out>
+    where
+        Out: CandidType + for<'de> Deserialize<'de>,
+    {
+        let data = self.call_and_wait(get_waiter()).await?;
+        let out = decode::<Out>(&data)?;
+        Ok(out)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Install state -
+// -----------------------------------------------------------------------------
+
+/// Marker for a canister that has code installed.
+pub struct Installed;
+
+/// A canister without any code installed, as returned by
+/// [`Canister::create_empty_canister`].
+///
+/// This can't be queried or updated, as there is no code to run.
+/// Call [`UninstalledCanister::install_code`] to get a `Canister<Installed>`.
+///
+/// ```
+/// # async fn run(agent: &ic_agent::Agent, bytecode: Vec<u8>) {
+/// use ic_test_utils::canister::Canister;
+///
+/// let wallet = Canister::new_wallet(agent, "bob", None).unwrap();
+/// let empty = wallet.create_empty_canister(1_000_000, None).await.unwrap();
+/// let canister = empty.install_code(bytecode, ()).await.unwrap();
+/// # }
+/// ```
+pub struct UninstalledCanister<'agent> {
+    id: Principal,
+    agent: &'agent Agent,
+}
+
+impl<'agent> UninstalledCanister<'agent> {
+    /// Create a new uninstalled canister with a given `Principal`
+    pub fn new(id: Principal, agent: &'agent Agent) -> Self {
+        Self { id, agent }
+    }
+
+    /// The id of the canister
+    pub fn principal(&self) -> &Principal {
+        &self.id
+    }
+
+    /// Install code in the canister through the management canister
+    pub async fn install_code<A: ArgumentEncoder>(
+        self,
+        bytecode: Vec<u8>,
+        arg: A,
+    ) -> Result<Canister<'agent, Installed>> {
+        let management = Canister::new_management(self.agent);
+        management
+            .install_code(self.agent, self.id, bytecode, arg)
+            .await?;
+        Ok(Canister::new(self.id, self.agent))
+    }
 }
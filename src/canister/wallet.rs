@@ -12,31 +12,80 @@
 //! # }
 //! ```
 use std::fs::read_to_string;
+use std::path::PathBuf;
 
-use candid::{CandidType, Decode, Deserialize, Encode};
-use ic_agent::ic_types::Principal;
-use ic_agent::{agent::UpdateBuilder, Agent};
+use candid::{CandidType, Deserialize, Encode, Principal};
+use futures_util::future::try_join_all;
+use ic_agent::{
+    agent::{AgentError, UpdateBuilder},
+    Agent,
+};
 
-use super::Canister;
-use crate::get_waiter;
+use super::management::RawCanisterSettings;
+use super::{decode, decode_args, Canister, CanisterSettings, UninstalledCanister};
+use crate::{find_up, get_waiter};
 use crate::{Error, Result};
 
 pub const WALLET_IDS_PATH: &str = "../../.dfx/local/wallets.json";
+const WALLETS_PATH_ENV: &str = "DFX_WALLETS_PATH";
 
+#[allow(clippy::result_large_err)]
 fn get_wallet_principal<'a>(
     account_name: impl AsRef<str>,
     wallet_id_path: impl Into<Option<&'a str>>,
+    network: &str,
 ) -> Result<Principal> {
-    let wallet_id_path = wallet_id_path.into().unwrap_or(WALLET_IDS_PATH);
-    let json_str = read_to_string(wallet_id_path)?;
+    let wallet_id_path = wallets_path(wallet_id_path.into());
+    let json_str = match read_to_string(&wallet_id_path) {
+        Ok(json_str) => json_str,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::WalletsFileNotFound(wallet_id_path))
+        }
+        Err(source) => {
+            return Err(Error::FileRead {
+                path: wallet_id_path,
+                source,
+            })
+        }
+    };
     let json = serde_json::from_str::<serde_json::Value>(&json_str)?;
-    let id = json["identities"][account_name.as_ref()]["local"]
+    let account_name = account_name.as_ref();
+    let identities = &json["identities"];
+    if identities.get(account_name).is_none() {
+        let available = identities
+            .as_object()
+            .map(|identities| identities.keys().cloned().collect())
+            .unwrap_or_default();
+        return Err(Error::AccountNotFound {
+            requested: account_name.to_string(),
+            available,
+        });
+    }
+    let id = identities[account_name][network]
         .as_str()
         .ok_or(Error::InvalidOrMissingAccountInJson)?;
     let principal = Principal::from_text(id)?;
     Ok(principal)
 }
 
+// The `DFX_WALLETS_PATH` env var takes precedence over the given path,
+// which takes precedence over the default. If the default doesn't exist,
+// the closest `.dfx/local/wallets.json` up from the current directory is used.
+fn wallets_path(wallet_id_path: Option<&str>) -> PathBuf {
+    if let Some(path) = std::env::var_os(WALLETS_PATH_ENV) {
+        return PathBuf::from(path);
+    }
+    if let Some(path) = wallet_id_path {
+        return PathBuf::from(path);
+    }
+
+    let default = PathBuf::from(WALLET_IDS_PATH);
+    if default.is_file() {
+        return default;
+    }
+    find_up(".dfx/local/wallets.json").unwrap_or(default)
+}
+
 /// The balance result of a `Wallet::balance` call.
 #[derive(Debug, CandidType, Deserialize)]
 pub struct BalanceResult {
@@ -51,8 +100,57 @@ pub struct CallResult {
     pub payload: Vec<u8>,
 }
 
-#[derive(CandidType, Deserialize)]
+/// The reason a call forwarded through a wallet failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletCallError {
+    /// The wallet does not have enough cycles to make the call
+    OutOfCycles(String),
+    /// The target canister trapped
+    TargetTrapped(String),
+    /// The call was rejected for any other reason
+    Rejected(String),
+}
+
+impl WalletCallError {
+    // Classify the error message returned by `wallet_call`.
+    fn from_message(message: String) -> Self {
+        let lower = message.to_lowercase();
+        if lower.contains("out of cycles")
+            || lower.contains("insufficient cycles")
+            || lower.contains("not enough cycles")
+        {
+            Self::OutOfCycles(message)
+        } else if lower.contains("trapped") {
+            Self::TargetTrapped(message)
+        } else {
+            Self::Rejected(message)
+        }
+    }
+
+    /// The error message returned by the wallet
+    pub fn message(&self) -> &str {
+        match self {
+            Self::OutOfCycles(message) | Self::TargetTrapped(message) | Self::Rejected(message) => {
+                message
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for WalletCallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfCycles(message) => write!(f, "out of cycles: {}", message),
+            Self::TargetTrapped(message) => write!(f, "target trapped: {}", message),
+            Self::Rejected(message) => write!(f, "rejected: {}", message),
+        }
+    }
+}
+
+/// The result of a `Wallet::create_canister_full` call.
+#[derive(Debug, CandidType, Deserialize)]
 pub struct CreateResult {
+    /// Id of the created canister
     pub canister_id: Principal,
 }
 
@@ -65,19 +163,45 @@ struct CallForwardArgs {
     cycles: u64,
 }
 
+#[derive(Debug, CandidType, Deserialize)]
+struct CallForward128Args {
+    canister: Principal,
+    method_name: String,
+    #[serde(with = "serde_bytes")]
+    args: Vec<u8>,
+    cycles: u128,
+}
+
 /// Wallet for cycles
 pub struct Wallet;
 
 impl<'agent> Canister<'agent, Wallet> {
     /// Create a new wallet canister.
     /// If the `wallet_id_path` is `None` then the default [`WALLET_IDS_PATH`] will
-    /// be used.
+    /// be used, or if that doesn't exist the closest `.dfx/local/wallets.json`
+    /// found walking up from the current directory.
+    ///
+    /// The `DFX_WALLETS_PATH` env var, if set, overrides both.
+    #[allow(clippy::result_large_err)]
     pub fn new_wallet<'a>(
         agent: &'agent Agent,
         account_name: impl AsRef<str>,
         wallet_id_path: impl Into<Option<&'a str>>,
     ) -> Result<Self> {
-        let id = get_wallet_principal(account_name, wallet_id_path)?;
+        Self::new_wallet_with_network(agent, account_name, wallet_id_path, "local")
+    }
+
+    /// Create a new wallet canister for a dfx network other than `local`,
+    /// e.g. `ic` or a named testnet.
+    /// The wallets json is found the same way as for [`Canister::new_wallet`].
+    #[allow(clippy::result_large_err)]
+    pub fn new_wallet_with_network<'a>(
+        agent: &'agent Agent,
+        account_name: impl AsRef<str>,
+        wallet_id_path: impl Into<Option<&'a str>>,
+        network: &str,
+    ) -> Result<Self> {
+        let id = get_wallet_principal(account_name, wallet_id_path, network)?;
         let inst = Self::new(id, agent);
         Ok(inst)
     }
@@ -87,10 +211,130 @@ impl<'agent> Canister<'agent, Wallet> {
         let mut builder = self.agent.query(self.principal(), "wallet_balance");
         builder.with_arg(&Encode!(&())?);
         let data = builder.call().await?;
-        let balance = Decode!(&data, BalanceResult)?;
+        let balance = decode::<BalanceResult>(&data)?;
         Ok(balance)
     }
 
+    /// Get the current balance of a canister as a 128-bit amount.
+    /// Wallets that don't support `wallet_balance128` fall back to [`Canister::balance`].
+    pub async fn balance128(&self) -> Result<u128> {
+        #[derive(CandidType, Deserialize)]
+        struct Balance128Result {
+            amount: u128,
+        }
+
+        let mut builder = self.agent.query(self.principal(), "wallet_balance128");
+        builder.with_arg(&Encode!(&())?);
+        match builder.call().await {
+            Ok(data) => {
+                let balance = decode::<Balance128Result>(&data)?;
+                Ok(balance.amount)
+            }
+            Err(AgentError::ReplicaError { .. }) => {
+                let balance = self.balance().await?;
+                Ok(balance.amount.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// List the canisters created by the wallet.
+    pub async fn managed_canisters(&self) -> Result<Vec<Principal>> {
+        #[derive(CandidType)]
+        struct ListCanistersArgs {
+            from: Option<u32>,
+            to: Option<u32>,
+        }
+
+        #[derive(CandidType, Deserialize)]
+        struct ManagedCanisterInfo {
+            id: Principal,
+        }
+
+        let args = ListCanistersArgs {
+            from: Some(0),
+            to: None,
+        };
+        let mut builder = self.agent.query(self.principal(), "list_managed_canisters");
+        builder.with_arg(&Encode!(&args)?);
+        let data = builder.call().await?;
+        let (canisters, _total): (Vec<ManagedCanisterInfo>, u32) = decode_args(&data)?;
+        Ok(canisters.into_iter().map(|info| info.id).collect())
+    }
+
+    /// Add a custodian to the wallet.
+    pub async fn authorize(&self, principal: Principal) -> Result<()> {
+        let mut builder = self.agent.update(self.principal(), "authorize");
+        builder.with_arg(&Encode!(&principal)?);
+        builder.call_and_wait(get_waiter()).await?;
+        Ok(())
+    }
+
+    /// Remove a custodian from the wallet.
+    pub async fn deauthorize(&self, principal: Principal) -> Result<()> {
+        let mut builder = self.agent.update(self.principal(), "deauthorize");
+        builder.with_arg(&Encode!(&principal)?);
+        let data = builder.call_and_wait(get_waiter()).await?;
+        decode::<std::result::Result<(), String>>(&data)??;
+        Ok(())
+    }
+
+    /// Get the custodians of the wallet.
+    pub async fn get_custodians(&self) -> Result<Vec<Principal>> {
+        let mut builder = self.agent.query(self.principal(), "get_custodians");
+        builder.with_arg(&Encode!()?);
+        let data = builder.call().await?;
+        let custodians = decode::<Vec<Principal>>(&data)?;
+        Ok(custodians)
+    }
+
+    /// Upgrade the wallet to a new wallet wasm module.
+    ///
+    /// The upgrade is made directly through the management canister rather
+    /// than through the wallet itself, so the reply doesn't depend on the
+    /// wallet surviving its own upgrade. This requires the agent's identity to
+    /// be a controller of the wallet, which is the case for dfx wallets.
+    pub async fn upgrade_wallet(&self, wallet_wasm: Vec<u8>) -> Result<()> {
+        Canister::new_management(self.agent)
+            .upgrade_code(self.agent, self.id, wallet_wasm, ())
+            .await
+    }
+
+    /// Get the name of the wallet, or `None` if no name is set.
+    pub async fn get_name(&self) -> Result<Option<String>> {
+        let mut builder = self.agent.query(self.principal(), "name");
+        builder.with_arg(&Encode!()?);
+        let data = builder.call().await?;
+        let name = decode::<Option<String>>(&data)?;
+        Ok(name)
+    }
+
+    /// Set the name of the wallet.
+    pub async fn set_name(&self, name: String) -> Result<()> {
+        let mut builder = self.agent.update(self.principal(), "set_name");
+        builder.with_arg(&Encode!(&name)?);
+        builder.call_and_wait(get_waiter()).await?;
+        Ok(())
+    }
+
+    /// Send cycles from the wallet to another canister.
+    pub async fn send_cycles(&self, to: Principal, cycles: u64) -> Result<()> {
+        #[derive(CandidType)]
+        struct In {
+            canister: Principal,
+            amount: u64,
+        }
+
+        let mut builder = self.agent.update(self.principal(), "wallet_send");
+        builder.with_arg(&Encode!(&In {
+            canister: to,
+            amount: cycles,
+        })?);
+        let data = builder.call_and_wait(get_waiter()).await?;
+        decode::<std::result::Result<(), String>>(&data)??;
+        Ok(())
+    }
+
     /// Forward a call through the wallet, so cycles can be spent.
     pub async fn call_forward(&self, call: UpdateBuilder<'_>, cycles: u64) -> Result<Vec<u8>> {
         let call_forward_args = CallForwardArgs {
@@ -102,13 +346,38 @@ impl<'agent> Canister<'agent, Wallet> {
         let mut builder = self.agent.update(self.principal(), "wallet_call");
         builder.with_arg(&Encode!(&call_forward_args)?);
         let data = builder.call_and_wait(get_waiter()).await?;
-        let val = Decode!(&data, std::result::Result<CallResult, String>)??;
+        let val = decode::<std::result::Result<CallResult, String>>(&data)?.map_err(|message| {
+            Error::WalletCallRejected {
+                method: call_forward_args.method_name,
+                canister: call_forward_args.canister,
+                reason: WalletCallError::from_message(message),
+            }
+        })?;
+        Ok(val.payload)
+    }
+
+    /// Forward a call through the wallet with a 128-bit amount of cycles.
+    /// This requires a wallet that supports `wallet_call128`.
+    pub async fn call_forward128(&self, call: UpdateBuilder<'_>, cycles: u128) -> Result<Vec<u8>> {
+        let call_forward_args = CallForward128Args {
+            canister: call.canister_id,
+            method_name: call.method_name,
+            args: call.arg,
+            cycles,
+        };
+        let mut builder = self.agent.update(self.principal(), "wallet_call128");
+        builder.with_arg(&Encode!(&call_forward_args)?);
+        let data = builder.call_and_wait(get_waiter()).await?;
+        let val = decode::<std::result::Result<CallResult, String>>(&data)?.map_err(|message| {
+            Error::WalletCallRejected {
+                method: call_forward_args.method_name,
+                canister: call_forward_args.canister,
+                reason: WalletCallError::from_message(message),
+            }
+        })?;
         Ok(val.payload)
     }
 
-    // There seem to be no use of compute allocation, memory allocation or freezing threshold.
-    // If they are needed in the future we can add them as they are just newtypes around numbers,
-    // and they should be sent along with the canister settings.
     /// Create an empty canister.
     /// This does not install the wasm code for the canister.
     /// To do that call [`Canister::install_code`] after creating a canister.
@@ -117,18 +386,66 @@ impl<'agent> Canister<'agent, Wallet> {
         cycles: u64,
         controllers: impl Into<Option<Vec<Principal>>>,
     ) -> Result<Principal> {
-        #[derive(Debug, CandidType, Deserialize)]
+        let result = self.create_canister_full(cycles, controllers).await?;
+        Ok(result.canister_id)
+    }
+
+    /// Create an empty canister, tracking in the type that no code is installed yet.
+    pub async fn create_empty_canister(
+        &self,
+        cycles: u64,
+        controllers: impl Into<Option<Vec<Principal>>>,
+    ) -> Result<UninstalledCanister<'agent>> {
+        let id = self.create_canister(cycles, controllers).await?;
+        Ok(UninstalledCanister::new(id, self.agent))
+    }
+
+    /// Create an empty canister, returning the full [`CreateResult`].
+    pub async fn create_canister_full(
+        &self,
+        cycles: u64,
+        controllers: impl Into<Option<Vec<Principal>>>,
+    ) -> Result<CreateResult> {
+        let settings = CanisterSettings {
+            controllers: controllers.into(),
+            ..Default::default()
+        };
+        self._create_canister(cycles, settings).await
+    }
+
+    /// Create `count` empty canisters concurrently, each with `cycles_each` cycles.
+    /// The principals are returned in the order the creates were issued.
+    pub async fn create_canisters(
+        &self,
+        count: usize,
+        cycles_each: u64,
+        controllers: impl Into<Option<Vec<Principal>>>,
+    ) -> Result<Vec<Principal>> {
+        let controllers = controllers.into();
+        let creates = (0..count).map(|_| self.create_canister(cycles_each, controllers.clone()));
+        try_join_all(creates).await
+    }
+
+    /// Create an empty canister with the given [`CanisterSettings`].
+    /// Any setting left as `None` uses the default value.
+    pub async fn create_canister_with_settings(
+        &self,
+        cycles: u64,
+        settings: CanisterSettings,
+    ) -> Result<Principal> {
+        let result = self._create_canister(cycles, settings).await?;
+        Ok(result.canister_id)
+    }
+
+    async fn _create_canister(
+        &self,
+        cycles: u64,
+        settings: CanisterSettings,
+    ) -> Result<CreateResult> {
+        #[derive(CandidType)]
         struct In {
             cycles: u64,
-            settings: CanisterSettings,
-        }
-
-        #[derive(Debug, CandidType, Deserialize)]
-        struct CanisterSettings {
-            controllers: Option<Vec<Principal>>,
-            compute_allocation: Option<u8>,
-            memory_allocation: Option<u64>,
-            freezing_threshold: Option<u64>,
+            settings: RawCanisterSettings,
         }
 
         let mut builder = self
@@ -136,25 +453,20 @@ impl<'agent> Canister<'agent, Wallet> {
             .update(self.principal(), "wallet_create_canister");
         let args = In {
             cycles,
-            settings: CanisterSettings {
-                controllers: controllers.into(),
-                compute_allocation: None,
-                memory_allocation: None,
-                freezing_threshold: None,
-            },
+            settings: settings.into(),
         };
         builder.with_arg(&Encode!(&args)?);
         let data = builder.call_and_wait(get_waiter()).await?;
-        let result = Decode!(&data, std::result::Result<CreateResult, String>)??;
-        Ok(result.canister_id)
+        let result = decode::<std::result::Result<CreateResult, String>>(&data)??;
+        Ok(result)
     }
 }
 
 // -----------------------------------------------------------------------------
-//     - TODO -
-//     Do we need even need these types?
+//     - Allocations -
 // -----------------------------------------------------------------------------
 
+/// Compute allocation of a canister, as a percentage between 0 and 100.
 #[derive(Copy, Clone, Debug)]
 pub struct ComputeAllocation(u8);
 
@@ -189,6 +501,8 @@ try_from_compute_alloc_decl!(i16);
 try_from_compute_alloc_decl!(i32);
 try_from_compute_alloc_decl!(i64);
 
+/// Memory allocation of a canister in bytes, up to 2^48 (256TB).
+#[derive(Copy, Clone, Debug)]
 pub struct MemoryAllocation(u64);
 
 impl std::convert::From<MemoryAllocation> for u64 {
@@ -221,3 +535,91 @@ try_from_memory_alloc_decl!(i8);
 try_from_memory_alloc_decl!(i16);
 try_from_memory_alloc_decl!(i32);
 try_from_memory_alloc_decl!(i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::ENV_LOCK;
+
+    #[test]
+    fn wallet_call_errors_are_classified() {
+        assert!(matches!(
+            WalletCallError::from_message("Canister has insufficient cycles".to_string()),
+            WalletCallError::OutOfCycles(_)
+        ));
+        assert!(matches!(
+            WalletCallError::from_message(
+                "An error happened during the call: 5: Canister trapped explicitly".to_string()
+            ),
+            WalletCallError::TargetTrapped(_)
+        ));
+        let rejected = WalletCallError::from_message("Method not found".to_string());
+        assert_eq!(
+            rejected,
+            WalletCallError::Rejected("Method not found".to_string())
+        );
+        assert_eq!(rejected.message(), "Method not found");
+    }
+
+    #[test]
+    fn wallets_path_precedence() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let wallets_path_env = std::env::var_os(WALLETS_PATH_ENV);
+
+        std::env::set_var(WALLETS_PATH_ENV, "/env/wallets.json");
+        assert_eq!(
+            wallets_path(Some("/arg/wallets.json")),
+            PathBuf::from("/env/wallets.json")
+        );
+        assert_eq!(wallets_path(None), PathBuf::from("/env/wallets.json"));
+
+        std::env::remove_var(WALLETS_PATH_ENV);
+        assert_eq!(
+            wallets_path(Some("/arg/wallets.json")),
+            PathBuf::from("/arg/wallets.json")
+        );
+        // Where the default resolves to depends on the current directory
+        assert!(wallets_path(None).ends_with(".dfx/local/wallets.json"));
+
+        if let Some(value) = wallets_path_env {
+            std::env::set_var(WALLETS_PATH_ENV, value);
+        }
+    }
+
+    #[test]
+    fn missing_accounts_list_the_available_ones() {
+        let path = std::env::temp_dir().join(format!("wallets-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"identities": {"alice": {"local": "aaaaa-aa"}, "bob-2": {"local": "aaaaa-aa"}}}"#,
+        )
+        .unwrap();
+
+        let _lock = ENV_LOCK.lock().unwrap();
+        let wallets_path_env = std::env::var_os(WALLETS_PATH_ENV);
+        std::env::remove_var(WALLETS_PATH_ENV);
+        let result = get_wallet_principal("bob", path.to_str(), "local");
+        let found = get_wallet_principal("alice", path.to_str(), "local");
+        let other_network = get_wallet_principal("alice", path.to_str(), "ic");
+        if let Some(value) = wallets_path_env {
+            std::env::set_var(WALLETS_PATH_ENV, value);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(Error::AccountNotFound {
+                requested,
+                available,
+            }) => {
+                assert_eq!(requested, "bob");
+                assert_eq!(available, vec!["alice".to_string(), "bob-2".to_string()]);
+            }
+            other => panic!("expected AccountNotFound, got {:?}", other),
+        }
+        assert_eq!(found.unwrap(), Principal::management_canister());
+        assert!(matches!(
+            other_network,
+            Err(Error::InvalidOrMissingAccountInJson)
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use ic_agent::AgentError;
+
 /// Generic result type
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,6 +10,19 @@ pub enum Error {
     #[error("Invalid or missing account name in json structure")]
     InvalidOrMissingAccountInJson,
 
+    /// Identity missing from the wallets json
+    #[error("Account `{requested}` not found, available accounts: {available:?}")]
+    AccountNotFound {
+        /// The account that was requested
+        requested: String,
+        /// The accounts in the json
+        available: Vec<String>,
+    },
+
+    /// Canister missing from the json
+    #[error("Invalid or missing canister name in json structure")]
+    InvalidOrMissingCanisterInJson,
+
     /// A principal error
     #[error("Principal error: {0}")]
     Principal(#[from] ic_agent::export::PrincipalError),
@@ -16,6 +31,15 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    /// Failed to read a file
+    #[error("Failed to read {path}: {source}")]
+    FileRead {
+        /// The path that was read
+        path: std::path::PathBuf,
+        /// The underlying IO error
+        source: std::io::Error,
+    },
+
     /// Std env var error
     #[error("Env var error: {0}")]
     EnvVar(#[from] std::env::VarError),
@@ -24,18 +48,48 @@ pub enum Error {
     #[error("Certificate not found: {0}")]
     CertNotFound(std::path::PathBuf),
 
+    /// Wasm module not found error
+    #[error("Wasm module not found: {0}")]
+    WasmNotFound(std::path::PathBuf),
+
+    /// The wallets json written by dfx was not found
+    #[error("Wallets file not found: {0}")]
+    WalletsFileNotFound(std::path::PathBuf),
+
+    /// Canister metadata not found error
+    #[error("Canister metadata not found: {0}")]
+    MetadataNotFound(String),
+
+    /// Canister metadata is private error
+    #[error("Canister metadata is private: {0}")]
+    MetadataPrivate(String),
+
     /// Serde json error
     #[error("Serde error: {0}")]
     Json(#[from] serde_json::Error),
 
     /// Agent error
+    // `AgentError` is large, which is why functions returning `Result`
+    // allow `clippy::result_large_err`. Boxing it would change this variant.
     #[error("Agent error: {0}")]
-    Agent(#[from] ic_agent::agent::agent_error::AgentError),
+    Agent(#[from] AgentError),
 
     /// Identity error
     #[error("Identity error: {0}")]
     Ident(#[from] ic_agent::identity::PemError),
 
+    /// The identity PEM file holds neither an Ed25519 nor a secp256k1 key
+    #[error("Unsupported identity type in {0}")]
+    UnsupportedIdentityType(std::path::PathBuf),
+
+    /// The port of the local replica could not be found
+    #[error("Could not find the port of the local replica")]
+    ReplicaPortNotFound,
+
+    /// No `dfx.json` was found in the current directory or its parents
+    #[error("Could not find a dfx project (dfx.json) in the current directory or its parents")]
+    DfxProjectNotFound,
+
     /// Missing configuration directory
     #[error("Failed to get config directory")]
     MissingConfig,
@@ -44,6 +98,71 @@ pub enum Error {
     #[error("Candid error: {0}")]
     Candid(#[from] candid::Error),
 
+    /// The ledger rejected a transfer
+    #[error("Ledger error: {0}")]
+    Ledger(#[from] crate::canister::TransferError),
+
+    /// The certificate of a reply did not verify against the root key
+    #[error("Certificate verification failed for a reply from {0}")]
+    CertificationFailed(candid::Principal),
+
+    /// A call failed, with the method and canister it was made for
+    #[error("Call to `{method}` for {canister} failed: {source}")]
+    Call {
+        /// The method that was called
+        method: String,
+        /// The canister that was called, or the call was made for
+        canister: candid::Principal,
+        /// The underlying error
+        source: Box<Error>,
+    },
+
+    /// A call forwarded through a wallet was rejected
+    #[error("Wallet call to `{method}` on {canister} failed, {reason}")]
+    WalletCallRejected {
+        /// The method that was called
+        method: String,
+        /// The canister that was called
+        canister: candid::Principal,
+        /// Why the call failed
+        reason: crate::canister::WalletCallError,
+    },
+
+    /// An ICRC-1 ledger rejected a transfer
+    #[error("ICRC-1 ledger error: {0}")]
+    Icrc1Ledger(#[from] crate::canister::icrc1::TransferError),
+
+    /// A canister is not in the expected state
+    #[error("Expected canister {canister} to be {expected:?} but it is {actual:?}")]
+    UnexpectedCanisterStatus {
+        /// The canister that was checked
+        canister: candid::Principal,
+        /// The expected status
+        expected: crate::canister::CanisterStatus,
+        /// The actual status
+        actual: crate::canister::CanisterStatus,
+    },
+
+    /// A reply could not be decoded
+    #[error("Failed to decode {bytes_len} bytes as {type_name}: {source}")]
+    Decode {
+        /// The type that was decoded
+        type_name: &'static str,
+        /// The size of the reply
+        bytes_len: usize,
+        /// The underlying candid error
+        source: candid::Error,
+    },
+
+    /// An operation did not complete in time
+    #[error("Timed out after {waited:?} waiting for {operation}")]
+    Timeout {
+        /// The operation that timed out
+        operation: String,
+        /// How long was waited
+        waited: std::time::Duration,
+    },
+
     /// Generic error as a String
     #[error("Generic: {0}")]
     Generic(String),
@@ -57,8 +176,53 @@ pub enum Error {
     InvalidMemorySize(u64),
 }
 
+impl Error {
+    /// Whether the error is likely transient, so the same call may succeed
+    /// when retried.
+    ///
+    /// Retryable errors are:
+    /// * transport errors, e.g. the connection to the replica was refused
+    /// * HTTP errors with a 5xx status or `429 Too Many Requests`
+    ///
+    /// The agent timing out while waiting for a response is not retryable, as
+    /// the update call may already have been executed.
+    ///
+    /// An [`Error::Call`] is retryable if the error it wraps is. Everything
+    /// else, e.g. a rejected update call or a candid error, is considered fatal.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Call { source, .. } => source.is_retryable(),
+            Self::Agent(AgentError::TransportError(_)) => true,
+            Self::Agent(AgentError::HttpError(payload)) => {
+                payload.status >= 500 || payload.status == 429
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<String> for Error {
     fn from(s: String) -> Self {
         Self::Generic(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_errors_are_retryable_if_their_source_is() {
+        let call = |source: Error| Error::Call {
+            method: "greet".to_string(),
+            canister: candid::Principal::anonymous(),
+            source: Box::new(source),
+        };
+
+        let transport = Error::from(AgentError::TransportError("refused".into()));
+        assert!(transport.is_retryable());
+        assert!(call(transport).is_retryable());
+        assert!(!call(Error::Generic("rejected".to_string())).is_retryable());
+        assert!(!Error::from(AgentError::TimeoutWaitingForResponse()).is_retryable());
+    }
+}
@@ -1,43 +1,210 @@
 #![deny(missing_docs)]
 #![doc = include_str!("../README.md")]
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
 use candid::utils::ArgumentEncoder;
-use ic_agent::ic_types::Principal;
-use ic_agent::identity::BasicIdentity;
-use ic_agent::{agent::http_transport::ReqwestHttpReplicaV2Transport, identity::PemError};
+use garcon::Waiter;
+use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
+use ic_agent::identity::{AnonymousIdentity, BasicIdentity, Secp256k1Identity};
+use ic_agent::Identity;
+use ring::signature::Ed25519KeyPair;
 
+pub use candid::Principal;
 pub use ic_agent::Agent;
 
 mod errors;
 pub use errors::{Error, Result};
 
 pub mod canister;
+#[cfg(feature = "dfx-replica")]
+pub mod replica;
 
-pub use canister::{Canister, Management, ManagementCanister, Wallet, WalletCanister};
+pub use canister::{
+    Canister, Ledger, LedgerCanister, Management, ManagementCanister, Wallet, WalletCanister,
+};
 
 const URL: &str = "http://localhost:8000";
+// Where dfx writes the port of the local replica, newest layout first.
+const WEBSERVER_PORT_PATHS: [&str; 2] = [
+    ".dfx/network/local/webserver-port",
+    ".dfx/local/webserver-port",
+];
+
+/// Get the url of the local replica.
+///
+/// The port is read from the `webserver-port` file that dfx writes to
+/// `.dfx/network/local` (or `.dfx/local` for older dfx), looking in the
+/// current directory and then each of its parents. The closest project wins,
+/// whichever layout it uses. If that is missing the
+/// `local` network in `networks.json` in the dfx config directory is used
+/// (see [`get_identity`]).
+#[allow(clippy::result_large_err)]
+pub fn local_replica_url() -> Result<String> {
+    if let Some(path) = find_up_any(&WEBSERVER_PORT_PATHS) {
+        if let Ok(port) = std::fs::read_to_string(path) {
+            if let Ok(port) = port.trim().parse::<u16>() {
+                return Ok(format!("http://localhost:{}", port));
+            }
+        }
+    }
+
+    let networks_path = dfx_config_dir()?.join("networks.json");
+    let json_str =
+        std::fs::read_to_string(&networks_path).map_err(|_| Error::ReplicaPortNotFound)?;
+    let json = serde_json::from_str::<serde_json::Value>(&json_str)?;
+    let bind = json["local"]["bind"]
+        .as_str()
+        .ok_or(Error::ReplicaPortNotFound)?;
+    Ok(format!("http://{}", bind))
+}
 
 /// Get the identity for an account.
 /// This is useful for testing.
 ///
-/// If this is ever needed outside of `get_agent` just make this
-/// function public.
-pub fn get_identity(account_name: impl AsRef<Path>) -> Result<BasicIdentity> {
-    let mut ident_path = dirs::home_dir().ok_or(crate::Error::MissingConfig)?;
-    ident_path.push(".config");
-    ident_path.push("dfx/identity");
+/// Both Ed25519 and secp256k1 identities are supported,
+/// the key type is detected from the PEM header.
+///
+/// Identities are read from the dfx config directory, which like dfx is
+/// `$DFX_CONFIG_ROOT/.config/dfx` if `DFX_CONFIG_ROOT` is set, otherwise
+/// `$XDG_CONFIG_HOME/dfx`, falling back to `~/.config/dfx`.
+#[allow(clippy::result_large_err)]
+pub fn get_identity(account_name: impl AsRef<Path>) -> Result<Box<dyn Identity>> {
+    let mut ident_path = identity_dir()?;
     ident_path.push(account_name);
     ident_path.push("identity.pem");
-    match BasicIdentity::from_pem_file(&ident_path) {
-        Ok(identity) => Ok(identity),
-        Err(PemError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
-            Err(Error::CertNotFound(ident_path))
+    let pem = match std::fs::read_to_string(&ident_path) {
+        Ok(pem) => pem,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::CertNotFound(ident_path))
+        }
+        Err(source) => {
+            return Err(Error::FileRead {
+                path: ident_path,
+                source,
+            })
         }
-        Err(err) => Err(Error::from(err)),
+    };
+
+    if pem.contains("BEGIN EC PRIVATE KEY") {
+        Ok(Box::new(Secp256k1Identity::from_pem(pem.as_bytes())?))
+    } else if pem.contains("BEGIN PRIVATE KEY") {
+        Ok(Box::new(BasicIdentity::from_pem(pem.as_bytes())?))
+    } else {
+        Err(Error::UnsupportedIdentityType(ident_path))
     }
 }
 
+/// List the names of all available dfx identities.
+#[allow(clippy::result_large_err)]
+pub fn list_identities() -> Result<Vec<String>> {
+    let entries = match std::fs::read_dir(identity_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::from(e)),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if !path.join("identity.pem").is_file() {
+            continue;
+        }
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Find `relative` in the current directory or the closest of its parents.
+pub(crate) fn find_up(relative: impl AsRef<Path>) -> Option<PathBuf> {
+    find_up_any(&[relative])
+}
+
+// Like `find_up`, but looks for each of `relatives` in a directory before
+// moving on to its parent.
+pub(crate) fn find_up_any(relatives: &[impl AsRef<Path>]) -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .flat_map(|dir| relatives.iter().map(move |relative| dir.join(relative)))
+        .find(|path| path.is_file())
+}
+
+#[allow(clippy::result_large_err)]
+fn identity_dir() -> Result<PathBuf> {
+    Ok(dfx_config_dir()?.join("identity"))
+}
+
+#[allow(clippy::result_large_err)]
+fn dfx_config_dir() -> Result<PathBuf> {
+    if let Some(root) = std::env::var_os("DFX_CONFIG_ROOT") {
+        return Ok(PathBuf::from(root).join(".config/dfx"));
+    }
+    if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Ok(PathBuf::from(config_home).join("dfx"));
+    }
+    let mut path = dirs::home_dir().ok_or(crate::Error::MissingConfig)?;
+    path.push(".config/dfx");
+    Ok(path)
+}
+
+/// Generate a new random Ed25519 identity and its principal.
+/// Nothing is written to disk.
+pub fn random_identity() -> (BasicIdentity, Principal) {
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).expect("Failed to generate a key pair");
+    let key_pair =
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("Generated key pair is invalid");
+    let identity = BasicIdentity::from_key_pair(key_pair);
+    let principal = identity
+        .sender()
+        .expect("Basic identity always has a sender");
+    (identity, principal)
+}
+
+/// Parse a principal from its textual representation.
+///
+/// ```
+/// let principal = ic_test_utils::principal("2vxsx-fae").unwrap();
+/// assert_eq!(principal, ic_test_utils::Principal::anonymous());
+/// ```
+#[allow(clippy::result_large_err)]
+pub fn principal(text: &str) -> Result<Principal> {
+    let principal = Principal::from_text(text)?;
+    Ok(principal)
+}
+
+/// Get the principal of an identity by name.
+#[allow(clippy::result_large_err)]
+pub fn principal_for_identity(account_name: impl AsRef<Path>) -> Result<Principal> {
+    let identity = get_identity(account_name)?;
+    let principal = identity.sender()?;
+    Ok(principal)
+}
+
+/// Create an Ed25519 identity from a PEM string.
+#[allow(clippy::result_large_err)]
+pub fn identity_from_pem(pem: &str) -> Result<BasicIdentity> {
+    identity_from_pem_bytes(pem.as_bytes())
+}
+
+/// Create an Ed25519 identity from the bytes of a PEM file.
+#[allow(clippy::result_large_err)]
+pub fn identity_from_pem_bytes(pem: &[u8]) -> Result<BasicIdentity> {
+    let identity = BasicIdentity::from_pem(pem)?;
+    Ok(identity)
+}
+
+/// Get an agent from a PEM string, e.g. one provided through an env var.
+pub async fn get_agent_from_pem(pem: &str, url: Option<&str>) -> Result<Agent> {
+    let identity = identity_from_pem(pem)?;
+    get_agent_with_identity(identity, url).await
+}
+
 /// Get an agent by identity name.
 ///
 /// This is assuming there is an agent identity available.
@@ -50,26 +217,360 @@ pub fn get_identity(account_name: impl AsRef<Path>) -> Result<BasicIdentity> {
 /// ```
 pub async fn get_agent(name: impl Into<&str>, url: Option<&str>) -> Result<Agent> {
     let identity = get_identity(name.into())?;
+    build_agent(identity, url, true, None).await
+}
+
+/// Get an agent by identity name, reusing the root key fetched by an earlier
+/// call for the same url instead of fetching it again.
+///
+/// Call [`clear_root_key_cache`] after restarting the replica.
+pub async fn get_agent_cached(name: impl Into<&str>, url: Option<&str>) -> Result<Agent> {
+    let identity = get_identity(name.into())?;
+    let url = replica_url(url);
+    let agent = build_agent(identity, Some(&url), false, None).await?;
+
+    let slot = root_key_cache()
+        .lock()
+        .expect("poisoned root key cache")
+        .entry(url)
+        .or_default()
+        .clone();
+    set_cached_root_key(&agent, &slot).await?;
+    Ok(agent)
+}
+
+/// Clear the root keys cached by [`get_agent_cached`].
+pub fn clear_root_key_cache() {
+    root_key_cache()
+        .lock()
+        .expect("poisoned root key cache")
+        .clear();
+}
+
+fn root_key_cache() -> &'static Mutex<HashMap<String, Arc<RootKeySlot>>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Arc<RootKeySlot>>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Get an agent by identity name, retrying to fetch the root key
+/// up to `attempts` times with `delay` in between.
+///
+/// Only retryable errors (see [`Error::is_retryable`]) are retried, which is
+/// useful when the replica is still starting up. Any other error is returned
+/// immediately.
+pub async fn get_agent_retry(
+    name: impl Into<&str>,
+    url: Option<&str>,
+    attempts: usize,
+    delay: Duration,
+) -> Result<Agent> {
+    let identity = get_identity(name.into())?;
+    let agent = build_agent(identity, url, false, None).await?;
+
+    let mut waiter = garcon::Delay::throttle(delay);
+    waiter.start();
+    let mut attempt = 1;
+    loop {
+        match agent.fetch_root_key().await.map_err(Error::from) {
+            Ok(()) => return Ok(agent),
+            Err(e) if attempt < attempts && e.is_retryable() => {
+                attempt += 1;
+                // A throttle never times out
+                let _ = waiter.async_wait().await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Options for [`get_agent_opts`].
+#[derive(Debug, Clone, Copy)]
+pub struct AgentOptions<'a> {
+    /// Url of the replica. Defaults to the local replica.
+    pub url: Option<&'a str>,
+    /// Fetch the root key from the replica.
+    /// This should only be done against a local replica, never against mainnet.
+    pub fetch_root_key: bool,
+}
+
+impl Default for AgentOptions<'_> {
+    fn default() -> Self {
+        Self {
+            url: None,
+            fetch_root_key: true,
+        }
+    }
+}
+
+/// Get an agent by identity name with the given [`AgentOptions`].
+///
+/// ```
+/// # async fn run() {
+/// use ic_test_utils::{get_agent_opts, AgentOptions};
+///
+/// let options = AgentOptions {
+///     url: Some("https://ic0.app"),
+///     fetch_root_key: false,
+/// };
+/// let agent = get_agent_opts("bob", options).await.unwrap();
+/// # }
+/// ```
+pub async fn get_agent_opts(name: impl Into<&str>, options: AgentOptions<'_>) -> Result<Agent> {
+    let identity = get_identity(name.into())?;
+    build_agent(identity, options.url, options.fetch_root_key, None).await
+}
+
+/// Get an agent by identity name, using the provided `reqwest::Client`
+/// for the transport. This makes it possible to configure proxies,
+/// root certificates and timeouts.
+///
+/// The caller is responsible for configuring the client's TLS so that it
+/// can connect to the replica.
+pub async fn get_agent_with_client(
+    name: impl Into<&str>,
+    url: Option<&str>,
+    client: reqwest::Client,
+) -> Result<Agent> {
+    let identity = get_identity(name.into())?;
+    let transport = ReqwestHttpReplicaV2Transport::create_with_client(replica_url(url), client)?;
+    build_agent_with_transport(identity, transport, true, None).await
+}
+
+/// Get an agent with the provided identity.
+///
+/// Unlike [`get_agent`] this does not read the identity from disk,
+/// which makes it possible to use identities created in memory.
+pub async fn get_agent_with_identity(
+    identity: impl Identity + 'static,
+    url: Option<&str>,
+) -> Result<Agent> {
+    build_agent(Box::new(identity), url, true, None).await
+}
+
+/// Get an agent with an anonymous identity.
+///
+/// This is useful for query calls that don't require a caller identity.
+/// Note that the replica will reject most update calls made by this agent.
+pub async fn get_anonymous_agent(url: Option<&str>) -> Result<Agent> {
+    build_agent(Box::new(AnonymousIdentity), url, true, None).await
+}
+
+/// Builder for an [`Agent`], for when more control is needed than
+/// what [`get_agent`] offers.
+///
+/// If no identity name is set the agent uses an anonymous identity.
+///
+/// ```
+/// # async fn run() {
+/// use std::time::Duration;
+/// use ic_test_utils::AgentBuilder;
+///
+/// let agent = AgentBuilder::new()
+///     .identity_name("bob")
+///     .ingress_expiry(Duration::from_secs(60))
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AgentBuilder {
+    identity_name: Option<String>,
+    url: Option<String>,
+    ingress_expiry: Option<Duration>,
+    fetch_root_key: bool,
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        Self {
+            identity_name: None,
+            url: None,
+            ingress_expiry: None,
+            fetch_root_key: true,
+        }
+    }
+}
+
+impl AgentBuilder {
+    /// Create a new builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the dfx identity to use
+    pub fn identity_name(mut self, name: impl Into<String>) -> Self {
+        self.identity_name = Some(name.into());
+        self
+    }
+
+    /// Url of the replica. Defaults to the local replica.
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Ingress expiry of the requests made by the agent
+    pub fn ingress_expiry(mut self, duration: Duration) -> Self {
+        self.ingress_expiry = Some(duration);
+        self
+    }
+
+    /// Fetch the root key from the replica. Defaults to `true`.
+    /// This should only be done against a local replica, never against mainnet.
+    pub fn fetch_root_key(mut self, fetch_root_key: bool) -> Self {
+        self.fetch_root_key = fetch_root_key;
+        self
+    }
+
+    /// Build the agent
+    pub async fn build(self) -> Result<Agent> {
+        let identity = match self.identity_name {
+            Some(name) => get_identity(name)?,
+            None => Box::new(AnonymousIdentity),
+        };
+        build_agent(
+            identity,
+            self.url.as_deref(),
+            self.fetch_root_key,
+            self.ingress_expiry,
+        )
+        .await
+    }
+}
+
+/// A pool of agents sharing one `reqwest::Client`, so tests using several
+/// identities don't open a new connection per agent.
+///
+/// The root key is fetched by the first agent and reused by the rest.
+///
+/// ```
+/// # async fn run() {
+/// use ic_test_utils::AgentPool;
+///
+/// let pool = AgentPool::new(None);
+/// let alice = pool.agent_for("alice").await.unwrap();
+/// let bob = pool.agent_for("bob").await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AgentPool {
+    url: String,
+    client: reqwest::Client,
+    root_key: RootKeySlot,
+}
+
+impl AgentPool {
+    /// Create a new pool for the replica at `url`.
+    /// Defaults to the local replica.
+    pub fn new(url: Option<&str>) -> Self {
+        Self {
+            url: replica_url(url),
+            client: reqwest::Client::new(),
+            root_key: Mutex::new(None),
+        }
+    }
+
+    /// Get an agent by identity name, sharing the pool's client and root key.
+    pub async fn agent_for(&self, name: impl Into<&str>) -> Result<Agent> {
+        let identity = get_identity(name.into())?;
+        let transport =
+            ReqwestHttpReplicaV2Transport::create_with_client(&self.url, self.client.clone())?;
+        let agent = build_agent_with_transport(identity, transport, false, None).await?;
+        set_cached_root_key(&agent, &self.root_key).await?;
+        Ok(agent)
+    }
+}
+
+// A root key, once it has been fetched.
+type RootKeySlot = Mutex<Option<Vec<u8>>>;
 
-    let url = url.unwrap_or(URL);
-    let transport = ReqwestHttpReplicaV2Transport::create(url)?;
+// Set the root key in `slot` on the agent, fetching it first if the slot is empty.
+async fn set_cached_root_key(agent: &Agent, slot: &RootKeySlot) -> Result<()> {
+    let cached = slot.lock().expect("poisoned root key").clone();
+    let root_key = match cached {
+        Some(root_key) => root_key,
+        None => {
+            let root_key = fetch_root_key(agent).await?;
+            *slot.lock().expect("poisoned root key") = Some(root_key.clone());
+            root_key
+        }
+    };
+    agent.set_root_key(root_key)?;
+    Ok(())
+}
 
+// Fetch the root key of the replica the agent talks to.
+async fn fetch_root_key(agent: &Agent) -> Result<Vec<u8>> {
+    let status = agent.status().await?;
+    match status.root_key.clone() {
+        Some(root_key) => Ok(root_key),
+        None => Err(ic_agent::AgentError::NoRootKeyInStatus(status).into()),
+    }
+}
+
+async fn build_agent(
+    identity: Box<dyn Identity>,
+    url: Option<&str>,
+    fetch_root_key: bool,
+    ingress_expiry: Option<Duration>,
+) -> Result<Agent> {
+    let transport = ReqwestHttpReplicaV2Transport::create(replica_url(url))?;
+    build_agent_with_transport(identity, transport, fetch_root_key, ingress_expiry).await
+}
+
+async fn build_agent_with_transport(
+    identity: Box<dyn Identity>,
+    transport: ReqwestHttpReplicaV2Transport,
+    fetch_root_key: bool,
+    ingress_expiry: Option<Duration>,
+) -> Result<Agent> {
     let agent = Agent::builder()
         .with_transport(transport)
-        .with_identity(identity)
+        .with_boxed_identity(identity)
+        .with_ingress_expiry(ingress_expiry)
         .build()?;
 
-    agent.fetch_root_key().await?;
+    if fetch_root_key {
+        agent.fetch_root_key().await?;
+    }
 
     Ok(agent)
 }
 
+fn replica_url(url: Option<&str>) -> String {
+    match url {
+        Some(url) => url.to_string(),
+        None => local_replica_url().unwrap_or_else(|_| URL.to_string()),
+    }
+}
+
 /// Create a default `Delay` with a throttle of 500ms
 /// and a timout of five minutes.
 pub fn get_waiter() -> garcon::Delay {
+    get_waiter_with(Duration::from_millis(500), Duration::from_secs(60 * 5))
+}
+
+/// Create a `Delay` with a throttle of 50ms
+/// and a timeout of 30 seconds, suited for a local replica.
+pub fn get_waiter_fast() -> garcon::Delay {
+    get_waiter_with(Duration::from_millis(50), Duration::from_secs(30))
+}
+
+/// Create a `Delay` that backs off exponentially, starting at `initial`
+/// and multiplying the delay by `factor` after each poll.
+pub fn get_exponential_waiter(initial: Duration, factor: f32, timeout: Duration) -> garcon::Delay {
     garcon::Delay::builder()
-        .throttle(std::time::Duration::from_millis(500))
-        .timeout(std::time::Duration::from_secs(60 * 5))
+        .exponential_backoff(initial, factor)
+        .timeout(timeout)
+        .build()
+}
+
+/// Create a `Delay` with the given throttle and timeout.
+pub fn get_waiter_with(throttle: Duration, timeout: Duration) -> garcon::Delay {
+    garcon::Delay::builder()
+        .throttle(throttle)
+        .timeout(timeout)
         .build()
 }
 
@@ -90,3 +591,42 @@ pub async fn create_canister<T: ArgumentEncoder>(
         .await?;
     Ok(canister_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that change env vars hold this lock, as tests run in parallel.
+    pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn dfx_config_dir_precedence() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let config_root = std::env::var_os("DFX_CONFIG_ROOT");
+        let config_home = std::env::var_os("XDG_CONFIG_HOME");
+
+        std::env::set_var("DFX_CONFIG_ROOT", "/root-dir");
+        std::env::set_var("XDG_CONFIG_HOME", "/xdg");
+        assert_eq!(
+            dfx_config_dir().unwrap(),
+            PathBuf::from("/root-dir/.config/dfx")
+        );
+
+        std::env::remove_var("DFX_CONFIG_ROOT");
+        assert_eq!(dfx_config_dir().unwrap(), PathBuf::from("/xdg/dfx"));
+
+        std::env::remove_var("XDG_CONFIG_HOME");
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(dfx_config_dir().unwrap(), home.join(".config/dfx"));
+        assert_eq!(identity_dir().unwrap(), home.join(".config/dfx/identity"));
+
+        for (key, value) in [
+            ("DFX_CONFIG_ROOT", config_root),
+            ("XDG_CONFIG_HOME", config_home),
+        ] {
+            if let Some(value) = value {
+                std::env::set_var(key, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Start and stop a local dfx replica from a test.
+//!
+//! This requires the `dfx-replica` feature.
+//!
+//! ```
+//! # async fn run() {
+//! use ic_test_utils::{get_agent, replica::LocalReplica};
+//!
+//! // Blocks until the replica is up
+//! let replica = LocalReplica::start().unwrap();
+//! let agent = get_agent("bob", Some(replica.url())).await.unwrap();
+//! // The replica is stopped when `replica` is dropped
+//! # }
+//! ```
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::{find_up, local_replica_url, Error, Result};
+
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A local replica for the dfx project containing the current directory.
+///
+/// If no replica is running one is started with `dfx start`, and stopped
+/// again with `dfx stop` when this is dropped. A replica that was already
+/// running is used as is and left running.
+#[derive(Debug)]
+pub struct LocalReplica {
+    project_root: PathBuf,
+    url: String,
+    started: bool,
+}
+
+impl LocalReplica {
+    /// Start a local replica in the background and wait for it to accept
+    /// requests.
+    ///
+    /// If a replica is already running for the project it is reused with the
+    /// state it has. Otherwise a new one is started with `dfx start --clean`.
+    ///
+    /// This blocks the current thread for up to a minute while the replica
+    /// starts, so call it before entering an async runtime or from a blocking
+    /// task.
+    ///
+    /// The dfx project is the closest directory with a `dfx.json`, starting
+    /// from the current directory. If there is none [`Error::DfxProjectNotFound`]
+    /// is returned.
+    #[allow(clippy::result_large_err)]
+    pub fn start() -> Result<Self> {
+        let project_root = find_up("dfx.json")
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .ok_or(Error::DfxProjectNotFound)?;
+
+        if dfx(&project_root, &["ping"])?.status.success() {
+            let url = replica_url(&project_root)?;
+            return Ok(Self {
+                project_root,
+                url,
+                started: false,
+            });
+        }
+
+        // The replica inherits the output of `dfx start`, so it must not be
+        // piped or we would wait for the replica to exit.
+        let status = dfx_command(&project_root, &["start", "--background", "--clean"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(Error::Generic(format!("`dfx start` failed: {}", status)));
+        }
+        // Stops the replica if it doesn't come up in time
+        let mut replica = Self {
+            project_root,
+            url: String::new(),
+            started: true,
+        };
+
+        let started = Instant::now();
+        while !dfx(&replica.project_root, &["ping"])?.status.success() {
+            if started.elapsed() > START_TIMEOUT {
+                return Err(Error::Timeout {
+                    operation: "the local replica to start".to_string(),
+                    waited: START_TIMEOUT,
+                });
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        replica.url = replica_url(&replica.project_root)?;
+        Ok(replica)
+    }
+
+    /// Url of the replica
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Drop for LocalReplica {
+    fn drop(&mut self) {
+        if self.started {
+            let _ = dfx(&self.project_root, &["stop"]);
+        }
+    }
+}
+
+// Ask dfx for the port of the replica, falling back to the files dfx writes.
+#[allow(clippy::result_large_err)]
+fn replica_url(project_root: &Path) -> Result<String> {
+    let output = dfx(project_root, &["info", "webserver-port"])?;
+    if output.status.success() {
+        let port = String::from_utf8_lossy(&output.stdout);
+        if let Ok(port) = port.trim().parse::<u16>() {
+            return Ok(format!("http://localhost:{}", port));
+        }
+    }
+    local_replica_url()
+}
+
+#[allow(clippy::result_large_err)]
+fn dfx(project_root: &Path, args: &[&str]) -> Result<Output> {
+    let output = dfx_command(project_root, args).output()?;
+    Ok(output)
+}
+
+fn dfx_command(project_root: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new("dfx");
+    command
+        .args(args)
+        .current_dir(project_root)
+        .stdin(Stdio::null());
+    command
+}